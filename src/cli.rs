@@ -0,0 +1,211 @@
+// Command line parsing. Hand rolled to keep the dependency list short.
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::clock::TimeControl;
+use crate::engine::Difficulty;
+use crate::protocol::ColorChoice;
+
+pub const DEFAULT_ADDR: &str = "127.0.0.1:6969";
+/// Thinking time per move for a UCI engine in games without clocks.
+pub const DEFAULT_MOVETIME: Duration = Duration::from_millis(1000);
+
+pub const USAGE: &str = "\
+usage: eahla-gui [MODE] [OPTIONS]
+
+modes:
+  (none)                   play both colours on this machine
+  server [--bind ADDR]     wait for an opponent, ADDR defaults to 127.0.0.1:6969
+                           (use 0.0.0.0:PORT to accept other machines)
+  client [--connect ADDR]  connect to a server, ADDR defaults to 127.0.0.1:6969
+  replay FILE.pgn          step through a saved game with the arrow keys
+  uci ENGINE               play against a UCI engine, e.g. uci stockfish
+                           (quote ENGINE to pass it arguments)
+
+options:
+  --color white|black|random
+                           colour to play; a server with this set gets it, otherwise
+                           the client's wish decides (client default: white)
+  --fen FEN                start from this position instead of the initial one
+  --time MIN+SEC           play with clocks, e.g. 5+3 is five minutes plus three seconds
+                           a move; agreed like --color in network games
+  --ai                     play against the computer, which takes the colour --color
+                           does not give you (local games only)
+  --level easy|medium|hard how hard the computer plays (default: medium)
+  --movetime MS            thinking time per move for a UCI engine without --time
+                           (default: 1000)
+  --pgn-dir DIR            save every game as PGN in DIR when it ends or is left; without
+                           it games are only saved on demand, in the current directory
+  --tui                    play in the terminal instead of a window, moves are typed
+                           as E2E4 or SAN (not with replay)
+  -h, --help               show this text";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mode {
+    Local,
+    Server { bind: String },
+    Client { connect: String },
+    Replay { file: String },
+    Uci { engine: String, movetime: Duration },
+}
+
+impl Mode {
+    /// "server"/"client" for network games, "uci" for an engine behind the same channels,
+    /// None when playing locally.
+    pub fn network_name(&self) -> Option<&'static str> {
+        match self {
+            Mode::Local | Mode::Replay { .. } => None,
+            Mode::Server { .. } => Some("server"),
+            Mode::Client { .. } => Some("client"),
+            Mode::Uci { .. } => Some("uci"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Options {
+    pub mode: Mode,
+    pub start_fen: Option<String>,
+    pub color: Option<ColorChoice>,
+    pub time: Option<TimeControl>,
+    pub ai: Option<Difficulty>, // computer opponent in a local game
+    pub tui: bool, // terminal front end instead of the window
+    pub pgn_dir: Option<PathBuf>, // games are saved here as they end, not at all when None
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CliError {
+    Help,
+    Invalid(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Help => write!(f, "{}", USAGE),
+            CliError::Invalid(why) => write!(f, "error: {}\n\n{}", why, USAGE),
+        }
+    }
+}
+
+/// Parse the arguments after the program name.
+pub fn parse_args(args: &[String]) -> Result<Options, CliError> {
+    let mut mode_name: Option<&str> = None;
+    let mut operand: Option<String> = None; // replay's file or uci's engine
+    let mut addr: Option<(&str, String)> = None; // (flag, value)
+    let mut start_fen = None;
+    let mut color = None;
+    let mut time = None;
+    let mut ai = false;
+    let mut level = None;
+    let mut movetime = None;
+    let mut tui = false;
+    let mut pgn_dir = None;
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let mut value = |flag: &str| {
+            iter.next()
+                .cloned()
+                .ok_or_else(|| CliError::Invalid(format!("{} needs a value", flag)))
+        };
+        match arg.as_str() {
+            "-h" | "--help" => return Err(CliError::Help),
+            "--bind" => addr = Some(("--bind", value("--bind")?)),
+            "--connect" => addr = Some(("--connect", value("--connect")?)),
+            "--fen" => start_fen = Some(value("--fen")?),
+            "--pgn-dir" => pgn_dir = Some(PathBuf::from(value("--pgn-dir")?)),
+            "--color" => {
+                let choice = value("--color")?;
+                color = Some(ColorChoice::parse(&choice).ok_or_else(|| {
+                    CliError::Invalid(format!("bad color {}, use white, black or random", choice))
+                })?);
+            }
+            "--time" => time = Some(TimeControl::parse(&value("--time")?).map_err(CliError::Invalid)?),
+            "--ai" => ai = true,
+            "--tui" => tui = true,
+            "--movetime" => {
+                let ms = value("--movetime")?;
+                match ms.parse::<u64>() {
+                    Ok(ms) if ms > 0 => movetime = Some(Duration::from_millis(ms)),
+                    _ => return Err(CliError::Invalid(format!("bad move time {}, expected milliseconds", ms))),
+                }
+            }
+            "--level" => {
+                let name = value("--level")?;
+                level = Some(Difficulty::parse(&name).ok_or_else(|| {
+                    CliError::Invalid(format!("bad level {}, use easy, medium or hard", name))
+                })?);
+            }
+            flag if flag.starts_with('-') => {
+                return Err(CliError::Invalid(format!("unknown option {}", flag)));
+            }
+            name if mode_name.is_none() => mode_name = Some(name),
+            arg if matches!(mode_name, Some("replay" | "uci")) && operand.is_none() => operand = Some(arg.to_string()),
+            extra => return Err(CliError::Invalid(format!("unexpected argument {}", extra))),
+        }
+    }
+
+    let mode = match (mode_name, addr) {
+        (None, None) => Mode::Local,
+        (None, Some((flag, _))) => {
+            return Err(CliError::Invalid(format!("{} needs the server or client mode", flag)));
+        }
+        (Some("server"), None) => Mode::Server { bind: DEFAULT_ADDR.to_string() },
+        (Some("server"), Some(("--bind", a))) => Mode::Server { bind: validate_addr(&a)? },
+        (Some("client"), None) => Mode::Client { connect: DEFAULT_ADDR.to_string() },
+        (Some("client"), Some(("--connect", a))) => Mode::Client { connect: validate_addr(&a)? },
+        (Some("replay"), None) => match operand {
+            Some(file) => Mode::Replay { file },
+            None => return Err(CliError::Invalid("replay needs a PGN file".to_string())),
+        },
+        (Some("uci"), None) => match operand {
+            Some(engine) => Mode::Uci { engine, movetime: movetime.unwrap_or(DEFAULT_MOVETIME) },
+            None => return Err(CliError::Invalid("uci needs an engine to run".to_string())),
+        },
+        (Some(m @ ("server" | "client" | "replay" | "uci")), Some((flag, _))) => {
+            return Err(CliError::Invalid(format!("{} can not be used in {} mode", flag, m)));
+        }
+        (Some(other), _) => {
+            return Err(CliError::Invalid(format!("unknown mode {}, use server, client, replay or uci", other)));
+        }
+    };
+    if movetime.is_some() && !matches!(mode, Mode::Uci { .. }) {
+        return Err(CliError::Invalid("--movetime only works in uci mode".to_string()));
+    }
+
+    if let Mode::Replay { .. } = mode
+        && start_fen.is_some()
+    {
+        return Err(CliError::Invalid("--fen can not be used in replay mode, the PGN has the start position".to_string()));
+    }
+
+    let ai = match (ai, level) {
+        (false, Some(_)) => return Err(CliError::Invalid("--level needs --ai".to_string())),
+        (false, None) => None,
+        (true, level) => Some(level.unwrap_or(Difficulty::Medium)),
+    };
+    if ai.is_some() && mode != Mode::Local {
+        return Err(CliError::Invalid("--ai only works in a local game".to_string()));
+    }
+
+    if tui && matches!(mode, Mode::Replay { .. }) {
+        return Err(CliError::Invalid("--tui can not be used with replay".to_string()));
+    }
+
+    Ok(Options { mode, start_fen, color, time, ai, tui, pgn_dir })
+}
+
+/// Checks that an address looks like HOST:PORT, without resolving the host.
+fn validate_addr(addr: &str) -> Result<String, CliError> {
+    let bad = |why: &str| CliError::Invalid(format!("bad address {:?}: {}", addr, why));
+    let (host, port) = addr.rsplit_once(':').ok_or_else(|| bad("expected HOST:PORT"))?;
+    if host.is_empty() {
+        return Err(bad("missing host"));
+    }
+    match port.parse::<u16>() {
+        Ok(0) | Err(_) => Err(bad("port must be 1-65535")),
+        Ok(_) => Ok(addr.to_string()),
+    }
+}
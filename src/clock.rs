@@ -0,0 +1,125 @@
+// Chess clocks. Times are worked out from the Instant passed in, so nothing here
+// needs a timer thread.
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use hermanha_chess::Color;
+
+/// Time per player and the increment added after each of their moves, `--time 5+3`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TimeControl {
+    pub base: Duration,
+    pub increment: Duration,
+}
+
+impl TimeControl {
+    /// "MINUTES+SECONDS", e.g. "5+3", "0.5+0" or just "10" for no increment.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let bad = || format!("bad time control {:?}, expected MINUTES+SECONDS like 5+3", s);
+        let (minutes, seconds) = s.split_once('+').unwrap_or((s, "0"));
+        let minutes: f64 = minutes.parse().map_err(|_| bad())?;
+        let seconds: u64 = seconds.parse().map_err(|_| bad())?;
+        if !minutes.is_finite() || minutes <= 0.0 || minutes > 24.0 * 60.0 {
+            return Err(bad());
+        }
+        Ok(TimeControl {
+            base: Duration::from_secs_f64(minutes * 60.0),
+            increment: Duration::from_secs(seconds),
+        })
+    }
+}
+
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}+{}", self.base.as_secs_f64() / 60.0, self.increment.as_secs())
+    }
+}
+
+fn index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+/// Both players' clocks, at most one of them running.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    pub control: TimeControl,
+    remaining: [Duration; 2], // as of `running`'s Instant for the running side
+    running: Option<(Color, Instant)>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        Clock {
+            control,
+            remaining: [control.base; 2],
+            running: None,
+        }
+    }
+
+    pub fn running(&self) -> Option<Color> {
+        self.running.map(|(color, _)| color)
+    }
+
+    pub fn remaining(&self, color: Color, now: Instant) -> Duration {
+        let left = self.remaining[index(color)];
+        match self.running {
+            Some((running, since)) if running == color => left.saturating_sub(now.saturating_duration_since(since)),
+            _ => left,
+        }
+    }
+
+    /// Stop whichever clock runs and start `color`'s, without any increment.
+    pub fn start(&mut self, color: Color, now: Instant) {
+        self.stop(now);
+        self.running = Some((color, now));
+    }
+
+    pub fn stop(&mut self, now: Instant) {
+        if let Some((color, _)) = self.running {
+            self.remaining[index(color)] = self.remaining(color, now);
+            self.running = None;
+        }
+    }
+
+    /// `mover` finished a move: their clock stops and gets the increment, the other one starts.
+    pub fn press(&mut self, mover: Color, now: Instant) {
+        self.stop(now);
+        self.remaining[index(mover)] += self.control.increment;
+        let other = match mover {
+            Color::White => Color::Black,
+            Color::Black => Color::White,
+        };
+        self.running = Some((other, now));
+    }
+
+    /// Take over times from the opponent's clock; a running clock keeps running from here.
+    pub fn set(&mut self, color: Color, remaining: Duration, now: Instant) {
+        self.remaining[index(color)] = remaining;
+        if let Some((running, _)) = self.running
+            && running == color
+        {
+            self.running = Some((color, now));
+        }
+    }
+
+    /// The side whose time ran out, if any.
+    pub fn flagged(&self, now: Instant) -> Option<Color> {
+        [Color::White, Color::Black]
+            .into_iter()
+            .find(|&color| self.remaining(color, now).is_zero())
+    }
+}
+
+/// "4:59" for a clock face, with tenths in the last ten seconds ("9.8").
+pub fn format_time(time: Duration) -> String {
+    if time < Duration::from_secs(10) {
+        let tenths = time.as_millis() / 100;
+        format!("{}.{}", tenths / 10, tenths % 10)
+    } else {
+        let secs = time.as_secs();
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
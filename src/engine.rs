@@ -0,0 +1,219 @@
+// Computer opponent: alpha-beta search with a material and piece-square evaluation,
+// https://www.chessprogramming.org/Simplified_Evaluation_Function
+use hermanha_chess::{Board, Color, PieceType, Position};
+
+use crate::rules::{all_squares, is_in_check, is_promotion, legal_moves};
+
+/// How far the computer looks ahead.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+impl Difficulty {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Medium => "medium",
+            Difficulty::Hard => "hard",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "easy" => Some(Difficulty::Easy),
+            "medium" => Some(Difficulty::Medium),
+            "hard" => Some(Difficulty::Hard),
+            _ => None,
+        }
+    }
+
+    /// Search depth in plies.
+    pub fn depth(&self) -> u32 {
+        match self {
+            Difficulty::Easy => 1,
+            Difficulty::Medium => 2,
+            Difficulty::Hard => 3,
+        }
+    }
+}
+
+pub type EngineMove = (Position, Position, Option<PieceType>);
+
+const MATE: i32 = 100_000;
+
+fn piece_value(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::Pawn => 100,
+        PieceType::Knight => 320,
+        PieceType::Bishop => 330,
+        PieceType::Rook => 500,
+        PieceType::Queen => 900,
+        PieceType::King => 0,
+    }
+}
+
+// piece-square tables from White's side, a8 first like a printed board
+#[rustfmt::skip]
+const PAWN_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+    50, 50, 50, 50, 50, 50, 50, 50,
+    10, 10, 20, 30, 30, 20, 10, 10,
+     5,  5, 10, 25, 25, 10,  5,  5,
+     0,  0,  0, 20, 20,  0,  0,  0,
+     5, -5,-10,  0,  0,-10, -5,  5,
+     5, 10, 10,-20,-20, 10, 10,  5,
+     0,  0,  0,  0,  0,  0,  0,  0,
+];
+#[rustfmt::skip]
+const KNIGHT_TABLE: [i32; 64] = [
+    -50,-40,-30,-30,-30,-30,-40,-50,
+    -40,-20,  0,  0,  0,  0,-20,-40,
+    -30,  0, 10, 15, 15, 10,  0,-30,
+    -30,  5, 15, 20, 20, 15,  5,-30,
+    -30,  0, 15, 20, 20, 15,  0,-30,
+    -30,  5, 10, 15, 15, 10,  5,-30,
+    -40,-20,  0,  5,  5,  0,-20,-40,
+    -50,-40,-30,-30,-30,-30,-40,-50,
+];
+#[rustfmt::skip]
+const BISHOP_TABLE: [i32; 64] = [
+    -20,-10,-10,-10,-10,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5, 10, 10,  5,  0,-10,
+    -10,  5,  5, 10, 10,  5,  5,-10,
+    -10,  0, 10, 10, 10, 10,  0,-10,
+    -10, 10, 10, 10, 10, 10, 10,-10,
+    -10,  5,  0,  0,  0,  0,  5,-10,
+    -20,-10,-10,-10,-10,-10,-10,-20,
+];
+#[rustfmt::skip]
+const ROOK_TABLE: [i32; 64] = [
+     0,  0,  0,  0,  0,  0,  0,  0,
+     5, 10, 10, 10, 10, 10, 10,  5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+    -5,  0,  0,  0,  0,  0,  0, -5,
+     0,  0,  0,  5,  5,  0,  0,  0,
+];
+#[rustfmt::skip]
+const QUEEN_TABLE: [i32; 64] = [
+    -20,-10,-10, -5, -5,-10,-10,-20,
+    -10,  0,  0,  0,  0,  0,  0,-10,
+    -10,  0,  5,  5,  5,  5,  0,-10,
+     -5,  0,  5,  5,  5,  5,  0, -5,
+      0,  0,  5,  5,  5,  5,  0, -5,
+    -10,  5,  5,  5,  5,  5,  0,-10,
+    -10,  0,  5,  0,  0,  0,  0,-10,
+    -20,-10,-10, -5, -5,-10,-10,-20,
+];
+#[rustfmt::skip]
+const KING_TABLE: [i32; 64] = [
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -30,-40,-40,-50,-50,-40,-40,-30,
+    -20,-30,-30,-40,-40,-30,-30,-20,
+    -10,-20,-20,-20,-20,-20,-20,-10,
+     20, 20,  0,  0,  0,  0, 20, 20,
+     20, 30, 10,  0,  0, 10, 30, 20,
+];
+
+fn square_bonus(piece_type: PieceType, color: Color, pos: Position) -> i32 {
+    let table = match piece_type {
+        PieceType::Pawn => &PAWN_TABLE,
+        PieceType::Knight => &KNIGHT_TABLE,
+        PieceType::Bishop => &BISHOP_TABLE,
+        PieceType::Rook => &ROOK_TABLE,
+        PieceType::Queen => &QUEEN_TABLE,
+        PieceType::King => &KING_TABLE,
+    };
+    // Black reads the table upside down
+    let rank_from_top = match color {
+        Color::White => 7 - pos.row,
+        Color::Black => pos.row,
+    };
+    table[(rank_from_top * 8 + pos.col) as usize]
+}
+
+/// Material and piece placement, positive when White stands better.
+pub fn evaluate(board: &Board) -> i32 {
+    all_squares()
+        .filter_map(|pos| board.get(pos).map(|piece| (pos, piece)))
+        .map(|(pos, piece)| {
+            let score = piece_value(piece.piece_type) + square_bonus(piece.piece_type, piece.color, pos);
+            match piece.color {
+                Color::White => score,
+                Color::Black => -score,
+            }
+        })
+        .sum()
+}
+
+/// Legal moves with promotions spelled out, captures of big pieces first so the
+/// search cuts off sooner.
+fn ordered_moves(board: &Board) -> Vec<EngineMove> {
+    let mut moves: Vec<EngineMove> = Vec::new();
+    for (from, to) in legal_moves(board) {
+        if is_promotion(board, from, to) {
+            // rook and bishop promotions are hardly ever better than these
+            moves.push((from, to, Some(PieceType::Queen)));
+            moves.push((from, to, Some(PieceType::Knight)));
+        } else {
+            moves.push((from, to, None));
+        }
+    }
+    moves.sort_by_key(|&(_, to, promo)| {
+        let captured = board.get(to).map_or(0, |p| piece_value(p.piece_type));
+        -(captured + promo.map_or(0, piece_value))
+    });
+    moves
+}
+
+fn negamax(board: &Board, depth: u32, mut alpha: i32, beta: i32, ply: i32) -> i32 {
+    if depth == 0 {
+        let score = evaluate(board);
+        return if board.move_turn == Color::White { score } else { -score };
+    }
+    let moves = ordered_moves(board);
+    if moves.is_empty() {
+        // quicker mates score higher
+        return if is_in_check(board, board.move_turn) { -MATE + ply } else { 0 };
+    }
+    let mut best = -MATE;
+    for (from, to, promo) in moves {
+        let mut child = board.clone();
+        if child.move_piece(from, to, promo).is_err() {
+            continue;
+        }
+        let score = -negamax(&child, depth - 1, -beta, -alpha, ply + 1);
+        best = best.max(score);
+        alpha = alpha.max(score);
+        if alpha >= beta {
+            break;
+        }
+    }
+    best
+}
+
+/// The move the computer plays for the side to move, None when there is none.
+pub fn best_move(board: &Board, difficulty: Difficulty) -> Option<EngineMove> {
+    let mut best: Option<(i32, EngineMove)> = None;
+    let mut alpha = -MATE - 1;
+    for mv @ (from, to, promo) in ordered_moves(board) {
+        let mut child = board.clone();
+        if child.move_piece(from, to, promo).is_err() {
+            continue;
+        }
+        let score = -negamax(&child, difficulty.depth() - 1, -MATE - 1, -alpha, 1);
+        if best.is_none_or(|(best_score, _)| score > best_score) {
+            best = Some((score, mv));
+            alpha = alpha.max(score);
+        }
+    }
+    best.map(|(_, mv)| mv)
+}
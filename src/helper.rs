@@ -1,10 +1,21 @@
-use hermanha_chess::{Board, Color, PieceType, Position};
-use crate::protocol::{MoveMsg,square_to_position, position_to_square};
+use hermanha_chess::{Board, Color, MoveOk, Piece, PieceType, Position};
+use crate::rules;
+use crate::status::GameStatus;
+use crate::protocol::{MoveMsg, parse_move_str, position_to_square, square_to_position};
 
 pub fn print_board(board: &Board) {
-    for row in (0..8).rev() {
-        print!("{} ", row + 1);
-        for col in 0..8 {
+    print!("{}", board_to_text(board, false));
+}
+
+/// The board as text, one rank per line with White's pieces in capitals. With
+/// `black_at_bottom` it is drawn from Black's side.
+pub fn board_to_text(board: &Board, black_at_bottom: bool) -> String {
+    let rows: Vec<i8> = if black_at_bottom { (0..8).collect() } else { (0..8).rev().collect() };
+    let cols: Vec<i8> = if black_at_bottom { (0..8).rev().collect() } else { (0..8).collect() };
+    let mut out = String::new();
+    for &row in &rows {
+        out.push_str(&format!("{} ", row + 1));
+        for &col in &cols {
             let pos = Position::new(row, col);
             match board.get(pos) {
                 Some(piece) => {
@@ -20,40 +31,136 @@ pub fn print_board(board: &Board) {
                         Color::White => c,
                         Color::Black => c.to_ascii_lowercase(),
                     };
-                    print!("{} ", symbol);
+                    out.push(symbol);
+                    out.push(' ');
                 }
-                None => print!(". "),
+                None => out.push_str(". "),
             }
         }
-        println!();
+        out.push('\n');
+    }
+    out.push(' ');
+    for &col in &cols {
+        out.push(' ');
+        out.push((b'A' + col as u8) as char);
+    }
+    out.push('\n');
+    out
+}
+
+/// Castling availability as written in the third FEN field.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CastlingRights {
+    pub white_kingside: bool,
+    pub white_queenside: bool,
+    pub black_kingside: bool,
+    pub black_queenside: bool,
+}
+
+/// The FEN fields that `hermanha_chess::Board` does not give us, kept next to the board
+/// and updated with every move.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PositionInfo {
+    pub castling: CastlingRights,
+    pub en_passant: Option<Position>,
+    pub halfmove_clock: u32,
+    pub fullmove_number: u32,
+}
+
+impl PositionInfo {
+    pub fn start_pos() -> Self {
+        PositionInfo {
+            castling: CastlingRights {
+                white_kingside: true,
+                white_queenside: true,
+                black_kingside: true,
+                black_queenside: true,
+            },
+            en_passant: None,
+            halfmove_clock: 0,
+            fullmove_number: 1,
+        }
+    }
+
+    /// Update after `from -> to` has been played. `before` is the board before the move.
+    pub fn record_move(&mut self, before: &Board, from: Position, to: Position) {
+        let Some(piece) = before.get(from) else { return };
+        let is_pawn = piece.piece_type == PieceType::Pawn;
+        let captured = before.get(to).is_some() || (is_pawn && from.col != to.col);
+
+        if is_pawn || captured {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
+        }
+        if piece.color == Color::Black {
+            self.fullmove_number += 1;
+        }
+
+        self.en_passant = if is_pawn && (to.row - from.row).abs() == 2 {
+            Some(Position::new((from.row + to.row) / 2, from.col))
+        } else {
+            None
+        };
+
+        if piece.piece_type == PieceType::King {
+            match piece.color {
+                Color::White => {
+                    self.castling.white_kingside = false;
+                    self.castling.white_queenside = false;
+                }
+                Color::Black => {
+                    self.castling.black_kingside = false;
+                    self.castling.black_queenside = false;
+                }
+            }
+        }
+        // a rook leaving its corner or getting captured there loses that side
+        for corner in [from, to] {
+            match (corner.row, corner.col) {
+                (0, 0) => self.castling.white_queenside = false,
+                (0, 7) => self.castling.white_kingside = false,
+                (7, 0) => self.castling.black_queenside = false,
+                (7, 7) => self.castling.black_kingside = false,
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Play a move on the board and keep `info` in sync. Returns `MoveOk::NeedsPromotion`
+/// without touching anything when a promotion piece is missing.
+pub fn make_move(
+    board: &mut Board,
+    info: &mut PositionInfo,
+    from: Position,
+    to: Position,
+    promo: Option<PieceType>,
+) -> Result<MoveOk, String> {
+    let before = board.clone();
+    match board.move_piece(from, to, promo) {
+        Ok(MoveOk::NeedsPromotion) => Ok(MoveOk::NeedsPromotion),
+        Ok(ok) => {
+            info.record_move(&before, from, to);
+            Ok(ok)
+        }
+        Err(e) => Err(format!("illegal move: {:?}", e)),
     }
-    println!("  A B C D E F G H");
 }
 
-pub fn apply_message_to_board(board: &mut Board, msg: &MoveMsg) -> Result<(), String> {
+pub fn apply_message_to_board(board: &mut Board, info: &mut PositionInfo, msg: &MoveMsg) -> Result<(), String> {
     let mv = &msg.move_str;
     println!("Applying move: {}", mv);
-    if mv.len() != 5 { return Err("Bad move string".into()); }
-
-    let from = square_to_position(&mv[0..2]).ok_or("Bad from-square")?;
-    let to   = square_to_position(&mv[2..4]).ok_or("Bad to-square")?;
-    let promo = match &mv[4..5] {
-        "Q" | "q" => Some(PieceType::Queen),
-        "R" | "r" => Some(PieceType::Rook),
-        "B" | "b" => Some(PieceType::Bishop),
-        "N" | "n" | "k" => Some(PieceType::Knight), // "k" is ambiguous, here Knight
-        "0" => None,
-        _ => return Err("Bad promotion char".into()),
-    };
+    let (from, to, promo) = parse_move_str(mv).map_err(|e| e.to_string())?;
 
     println!("Trying move: {:?} -> {:?}", from, to);
-    match board.move_piece(from, to, promo) {
-        Ok(_) => {
+    match make_move(board, info, from, to, promo)? {
+        MoveOk::NeedsPromotion => Err("promotion piece missing".into()),
+        _ => {
             println!("Move applied: {:?} -> {:?}", from, to);
             //print_board(board);
             Ok(())
         }
-        Err(e) => Err(format!("illegal move: {:?}", e)),
     }
 }
 
@@ -62,6 +169,8 @@ pub fn board_move_to_message(
     to: Position,
     promo: Option<PieceType>,
     board: &Board,
+    info: &PositionInfo,
+    status: &GameStatus,
 ) -> MoveMsg {
     let mut move_str = format!(
         "{}{}",
@@ -79,12 +188,79 @@ pub fn board_move_to_message(
 
     MoveMsg {
         move_str,
-        game_state: "0-0".to_string(), // change later
-        fen: board_to_fen(board),      
+        game_state: status.game_state_str().to_string(),
+        fen: board_to_fen(board, info),
+    }
+}
+
+/// Identifies a position for the repetition rule: placement, side to move, castling and
+/// the en passant square, the latter only if a pawn can actually take there.
+pub fn position_key(board: &Board, info: &PositionInfo) -> String {
+    let mut info = info.clone();
+    if let Some(ep) = info.en_passant {
+        let capture_row = if board.move_turn == Color::White { ep.row - 1 } else { ep.row + 1 };
+        let can_capture = [ep.col - 1, ep.col + 1].iter().any(|&col| {
+            let from = Position::new(capture_row, col);
+            (0..8).contains(&col)
+                && matches!(board.get(from), Some(p) if p.piece_type == PieceType::Pawn && p.color == board.move_turn)
+                && rules::is_legal_move(board, from, ep)
+        });
+        if !can_capture {
+            info.en_passant = None;
+        }
     }
+    let fen = board_to_fen(board, &info);
+    fen.split_whitespace().take(4).collect::<Vec<_>>().join(" ")
 }
 
-pub fn board_to_fen(board: &Board) -> String {
+/// Compare our position with the FEN the peer sent along with its move. Only placement,
+/// side to move and castling are compared since implementations disagree on when to
+/// write the en passant square, and a placement-only FEN only checks the placement.
+pub fn verify_fen(board: &Board, info: &PositionInfo, fen: &str) -> Result<(), String> {
+    let ours = board_to_fen(board, info);
+    let ours: Vec<&str> = ours.split_whitespace().collect();
+    let theirs: Vec<&str> = fen.split_whitespace().collect();
+    for (i, field) in ["placement", "side to move", "castling"].iter().enumerate() {
+        if let Some(t) = theirs.get(i)
+            && *t != ours[i]
+        {
+            return Err(format!("{} differs, ours {:?} theirs {:?}", field, ours[i], t));
+        }
+    }
+    Ok(())
+}
+
+fn piece_to_fen_char(piece: Piece) -> char {
+    let symbol = match piece.piece_type {
+        PieceType::Pawn => 'P',
+        PieceType::Knight => 'N',
+        PieceType::Bishop => 'B',
+        PieceType::Rook => 'R',
+        PieceType::Queen => 'Q',
+        PieceType::King => 'K',
+    };
+    match piece.color {
+        Color::White => symbol,
+        Color::Black => symbol.to_ascii_lowercase(),
+    }
+}
+
+fn fen_char_to_piece(c: char) -> Option<Piece> {
+    let piece_type = match c.to_ascii_uppercase() {
+        'P' => PieceType::Pawn,
+        'N' => PieceType::Knight,
+        'B' => PieceType::Bishop,
+        'R' => PieceType::Rook,
+        'Q' => PieceType::Queen,
+        'K' => PieceType::King,
+        _ => return None,
+    };
+    let color = if c.is_ascii_uppercase() { Color::White } else { Color::Black };
+    Some(Piece { piece_type, color })
+}
+
+/// Just the piece placement field, e.g. "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR".
+pub fn board_to_fen_placement(board: &Board) -> String {
     let mut fen = String::new();
     for row in (0..8).rev() { // FEN goes 8->1
         let mut empty = 0;
@@ -95,19 +271,7 @@ pub fn board_to_fen(board: &Board) -> String {
                         fen.push_str(&empty.to_string());
                         empty = 0;
                     }
-                    let symbol = match piece.piece_type {
-                        PieceType::Pawn => 'P',
-                        PieceType::Knight => 'N',
-                        PieceType::Bishop => 'B',
-                        PieceType::Rook => 'R',
-                        PieceType::Queen => 'Q',
-                        PieceType::King => 'K',
-                    };
-                    let sym = match piece.color {
-                        Color::White => symbol,
-                        Color::Black => symbol.to_ascii_lowercase(),
-                    };
-                    fen.push(sym);
+                    fen.push(piece_to_fen_char(piece));
                 }
                 None => empty += 1,
             }
@@ -121,3 +285,206 @@ pub fn board_to_fen(board: &Board) -> String {
     }
     fen
 }
+
+/// Full six-field FEN of the position.
+pub fn board_to_fen(board: &Board, info: &PositionInfo) -> String {
+    let side = match board.move_turn {
+        Color::White => "w",
+        Color::Black => "b",
+    };
+
+    let mut castling = String::new();
+    if info.castling.white_kingside { castling.push('K'); }
+    if info.castling.white_queenside { castling.push('Q'); }
+    if info.castling.black_kingside { castling.push('k'); }
+    if info.castling.black_queenside { castling.push('q'); }
+    if castling.is_empty() { castling.push('-'); }
+
+    let en_passant = match info.en_passant {
+        Some(pos) => position_to_square(pos).to_ascii_lowercase(),
+        None => "-".to_string(),
+    };
+
+    format!(
+        "{} {} {} {} {} {}",
+        board_to_fen_placement(board),
+        side,
+        castling,
+        en_passant,
+        info.halfmove_clock,
+        info.fullmove_number
+    )
+}
+
+/// Build a board from a FEN. Only the placement field is required, missing fields
+/// default to white to move, no castling, no en passant and fresh clocks.
+///
+/// hermanha_chess can not be told the castling rights or the en passant square, it
+/// works them out from the moves it has seen. So the board gets there the same way:
+/// see `drop_castling_rights` and `replay_double_step`.
+pub fn fen_to_board(fen: &str) -> Result<(Board, PositionInfo), String> {
+    let fields: Vec<&str> = fen.split_whitespace().collect();
+    if fields.is_empty() || fields.len() > 6 {
+        return Err(format!("expected 1-6 FEN fields, got {}", fields.len()));
+    }
+
+    let mut placement: [[Option<Piece>; 8]; 8] = [[None; 8]; 8];
+    let ranks: Vec<&str> = fields[0].split('/').collect();
+    if ranks.len() != 8 {
+        return Err(format!("expected 8 ranks, got {}", ranks.len()));
+    }
+    for (i, rank) in ranks.iter().enumerate() {
+        let row = 7 - i;
+        let mut col = 0;
+        for c in rank.chars() {
+            if let Some(n) = c.to_digit(10).filter(|n| (1..=8).contains(n)) {
+                for _ in 0..n {
+                    col += 1;
+                }
+            } else {
+                let piece = fen_char_to_piece(c).ok_or(format!("bad piece {:?}", c))?;
+                if col < 8 {
+                    placement[row][col] = Some(piece);
+                }
+                col += 1;
+            }
+        }
+        if col != 8 {
+            return Err(format!("rank {} has {} squares", row + 1, col));
+        }
+    }
+
+    let turn = match fields.get(1).copied().unwrap_or("w") {
+        "w" => Color::White,
+        "b" => Color::Black,
+        other => return Err(format!("bad side to move {:?}", other)),
+    };
+
+    let mut info = PositionInfo::start_pos();
+    info.castling = CastlingRights {
+        white_kingside: false,
+        white_queenside: false,
+        black_kingside: false,
+        black_queenside: false,
+    };
+    let castling = fields.get(2).copied().unwrap_or("-");
+    if castling != "-" {
+        for c in castling.chars() {
+            match c {
+                'K' => info.castling.white_kingside = true,
+                'Q' => info.castling.white_queenside = true,
+                'k' => info.castling.black_kingside = true,
+                'q' => info.castling.black_queenside = true,
+                _ => return Err(format!("bad castling field {:?}", castling)),
+            }
+        }
+    }
+
+    let en_passant = fields.get(3).copied().unwrap_or("-");
+    if en_passant != "-" {
+        let pos = square_to_position(en_passant)
+            .filter(|pos| pos.row == 2 || pos.row == 5)
+            .ok_or(format!("bad en passant square {:?}", en_passant))?;
+        info.en_passant = Some(pos);
+    }
+
+    if let Some(halfmove) = fields.get(4) {
+        info.halfmove_clock = halfmove.parse().map_err(|_| format!("bad halfmove clock {:?}", halfmove))?;
+    }
+    if let Some(fullmove) = fields.get(5) {
+        info.fullmove_number = fullmove.parse().map_err(|_| format!("bad fullmove number {:?}", fullmove))?;
+    }
+
+    let mut board = Board::start_pos();
+    drop_castling_rights(&mut board, &placement, &info.castling)?;
+    board.squares = placement;
+    board.move_turn = turn;
+    if let Some(ep) = info.en_passant {
+        replay_double_step(&mut board, ep)?;
+    }
+    Ok((board, info))
+}
+
+fn is_piece(placement: &[[Option<Piece>; 8]; 8], pos: Position, piece_type: PieceType, color: Color) -> bool {
+    matches!(placement[pos.row as usize][pos.col as usize], Some(p) if p.piece_type == piece_type && p.color == color)
+}
+
+/// A fresh board lets a king and rook on their home squares castle. For every right the
+/// FEN leaves out although they are at home, move that rook off its corner first, on an
+/// empty board with only the kings and a knight per side to pass the move with.
+fn drop_castling_rights(
+    board: &mut Board,
+    placement: &[[Option<Piece>; 8]; 8],
+    rights: &CastlingRights,
+) -> Result<(), String> {
+    let corners = [
+        ('K', rights.white_kingside, Color::White, 0, 7),
+        ('Q', rights.white_queenside, Color::White, 0, 0),
+        ('k', rights.black_kingside, Color::Black, 7, 7),
+        ('q', rights.black_queenside, Color::Black, 7, 0),
+    ];
+    let mut drops = Vec::new();
+    for (name, right, color, row, col) in corners {
+        let (king, rook) = (Position::new(row, 4), Position::new(row, col));
+        let at_home = is_piece(placement, king, PieceType::King, color) && is_piece(placement, rook, PieceType::Rook, color);
+        if right && !at_home {
+            return Err(format!("castling right {} without the king and rook on their home squares", name));
+        }
+        if !right && at_home {
+            drops.push((color, rook));
+        }
+    }
+    if drops.is_empty() {
+        return Ok(());
+    }
+
+    board.squares = [[None; 8]; 8];
+    for (row, color) in [(0, Color::White), (7, Color::Black)] {
+        board.squares[row][4] = Some(Piece { piece_type: PieceType::King, color });
+    }
+    board.squares[2][2] = Some(Piece { piece_type: PieceType::Knight, color: Color::White });
+    board.squares[5][2] = Some(Piece { piece_type: PieceType::Knight, color: Color::Black });
+    board.move_turn = Color::White;
+    for (color, rook) in drops {
+        if board.move_turn != color {
+            pass_the_move(board)?;
+        }
+        board.squares[rook.row as usize][rook.col as usize] = Some(Piece { piece_type: PieceType::Rook, color });
+        let off = Position::new(if color == Color::White { 1 } else { 6 }, rook.col);
+        board.move_piece(rook, off, None).map_err(|e| format!("could not drop a castling right: {:?}", e))?;
+    }
+    Ok(())
+}
+
+/// Knight hop for the side to move, C3<->B5 for White and C6<->B4 for Black.
+fn pass_the_move(board: &mut Board) -> Result<(), String> {
+    let (home, away) = match board.move_turn {
+        Color::White => (Position::new(2, 2), Position::new(4, 1)),
+        Color::Black => (Position::new(5, 2), Position::new(3, 1)),
+    };
+    let (from, to) = if board.get(home).is_some() { (home, away) } else { (away, home) };
+    board.move_piece(from, to, None).map(|_| ()).map_err(|e| format!("could not pass the move: {:?}", e))
+}
+
+/// Let the pawn that passed `ep` make its double step again, so the board knows it may be
+/// taken en passant.
+fn replay_double_step(board: &mut Board, ep: Position) -> Result<(), String> {
+    let (pawn_color, ep_row, from_row, to_row) = match board.move_turn {
+        Color::White => (Color::Black, 5, 6, 4),
+        Color::Black => (Color::White, 2, 1, 3),
+    };
+    let (from, to) = (Position::new(from_row, ep.col), Position::new(to_row, ep.col));
+    let fits = ep.row == ep_row
+        && is_piece(&board.squares, to, PieceType::Pawn, pawn_color)
+        && board.get(ep).is_none()
+        && board.get(from).is_none();
+    if !fits {
+        return Err(format!("en passant square {} does not follow a double pawn step", position_to_square(ep)));
+    }
+    let turn = board.move_turn;
+    board.squares[from_row as usize][ep.col as usize] = board.squares[to_row as usize][ep.col as usize].take();
+    board.move_turn = pawn_color;
+    board.move_piece(from, to, None).map_err(|e| format!("en passant square {}: {:?}", position_to_square(ep), e))?;
+    board.move_turn = turn;
+    Ok(())
+}
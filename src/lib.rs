@@ -0,0 +1,13 @@
+pub mod network;
+pub mod protocol;
+pub mod helper;
+pub mod rules;
+pub mod status;
+pub mod cli;
+pub mod notation;
+pub mod pgn;
+pub mod clock;
+pub mod engine;
+pub mod uci;
+pub mod tui;
+pub mod session;
@@ -1,83 +1,417 @@
-mod network;
-mod protocol;
-mod helper;
-
 use ggez::event;
 use ggez::glam::*;
 use ggez::graphics;
 use ggez::graphics::PxScale;
 use ggez::graphics::TextFragment;
+use ggez::input::keyboard::{KeyCode, KeyInput, KeyMods};
 use ggez::{Context, GameResult};
 
 use hermanha_chess::{PieceType,Position,MoveOk,Color};
-use crate::protocol::{ProtocolMsg};
-use crate::helper::board_move_to_message;
+use eahla_gui::{cli, clock, network, pgn, rules, status, tui};
+use eahla_gui::cli::Mode;
+use eahla_gui::session::GameSession;
+use eahla_gui::status::GameOver;
 
 use std::env;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
-use std::time::Duration;
-
+use std::time::Instant;
+
+// board position on screen, the squares are SQUARE_SIZE wide
+const BOARD_X: f32 = 100.0;
+const SQUARE_SIZE: f32 = 75.0;
+
+// result overlay shown when the game is over
+const OVERLAY: graphics::Rect = graphics::Rect { x: 175.0, y: 200.0, w: 450.0, h: 200.0 };
+const NEW_GAME_BUTTON: graphics::Rect = graphics::Rect { x: 205.0, y: 320.0, w: 180.0, h: 50.0 };
+const QUIT_BUTTON: graphics::Rect = graphics::Rect { x: 415.0, y: 320.0, w: 180.0, h: 50.0 };
+
+// move list right of the board
+const PANEL_X: f32 = BOARD_X + 8.0 * SQUARE_SIZE + 20.0;
+const PANEL_LINE: f32 = 22.0;
+const PANEL_LINES: usize = 21;
+
+// undo/redo under the move list, local games only
+const UNDO_BUTTON: graphics::Rect = graphics::Rect { x: PANEL_X, y: 560.0, w: 100.0, h: 40.0 };
+const REDO_BUTTON: graphics::Rect = graphics::Rect { x: PANEL_X + 110.0, y: 560.0, w: 100.0, h: 40.0 };
+// network games ask the opponent instead
+const TAKEBACK_BUTTON: graphics::Rect = graphics::Rect { x: PANEL_X, y: 560.0, w: 210.0, h: 40.0 };
+const RESIGN_BUTTON: graphics::Rect = graphics::Rect { x: PANEL_X, y: 510.0, w: 100.0, h: 40.0 };
+const DRAW_BUTTON: graphics::Rect = graphics::Rect { x: PANEL_X + 110.0, y: 510.0, w: 100.0, h: 40.0 };
+const ACCEPT_BUTTON: graphics::Rect = NEW_GAME_BUTTON;
+const DECLINE_BUTTON: graphics::Rect = QUIT_BUTTON;
+
+const PROMOTION_PIECES: [PieceType; 4] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight];
+
+/// The window on top of a GameSession: clicks become moves, the session is drawn.
 struct MainState {
-    board: hermanha_chess::Board,
+    session: GameSession,
     selected_piece: Position,
-    net_writer: Option<std::sync::mpsc::Sender<ProtocolMsg>>,
-    net_reader: Option<std::sync::mpsc::Receiver<ProtocolMsg>>,
-    network_mode: Option<String>,
-    my_color: Color,
+    targets: Vec<Position>, // legal destinations of selected_piece
+    targets_for: Option<(Position, usize, bool)>, // (selected_piece, moves, can_move) targets were worked out for
+    pending_promotion: Option<(Position, Position)>, // pawn move waiting for the picker
+    moves_seen: usize, // length of the move list when we last looked
+    history_scroll: usize, // first line of the move list shown
+    flipped: bool, // view turned around with the F key
+    replay: Option<pgn::PgnGame>, // saved game being stepped through, see cli::Mode::Replay
+    resign_armed: bool, // resign was clicked once, the next click on it resigns
+    network_thread: Option<thread::JoinHandle<()>>, // talks to the opponent, joined when quitting
+    interrupted: Arc<AtomicBool>, // Ctrl-C was pressed in the terminal
 }
 
 impl MainState {
-    fn new(network_mode: Option<String>) -> GameResult<MainState> {
-        let board = hermanha_chess::Board::start_pos();
-        let my_color = match network_mode.as_deref() {
-            Some("client") => Color::White,  
-            Some("server") => Color::Black,  
-            _ => Color::White,               // fallback (single-player)
+    fn new(session: GameSession, interrupted: Arc<AtomicBool>) -> MainState {
+        MainState {
+            session,
+            selected_piece: Position { row: 4, col: 4 },
+            targets: Vec::new(),
+            targets_for: None,
+            pending_promotion: None,
+            moves_seen: 0,
+            history_scroll: 0,
+            flipped: false,
+            replay: None,
+            resign_armed: false,
+            network_thread: None,
+            interrupted,
+        }
+    }
+
+    /// Open a PGN file for stepping through, starting before the first move.
+    fn load_replay(&mut self, file: &str) -> GameResult {
+        let fail = |e: String| ggez::GameError::CustomError(format!("Can not replay {}: {}", file, e));
+        let text = std::fs::read_to_string(file).map_err(|e| fail(e.to_string()))?;
+        let game = pgn::parse_pgn(&text).map_err(fail)?;
+        self.session.load_replay(pgn::replay_game(&game).map_err(fail)?);
+        println!("Replaying {} - {}, {} moves", game.white, game.black, self.session.moves().len());
+        self.moves_seen = self.session.moves().len();
+        self.replay = Some(game);
+        self.step_replay(0);
+        Ok(())
+    }
+
+    /// Show the replayed game after `at` moves.
+    fn step_replay(&mut self, at: usize) {
+        self.session.show_position(at);
+
+        // keep the current move in view
+        if let Some(line) = self.current_move_line() {
+            if line < self.history_scroll {
+                self.history_scroll = line;
+            } else if line >= self.history_scroll + PANEL_LINES {
+                self.history_scroll = line + 1 - PANEL_LINES;
+            }
+        }
+    }
+
+    /// Line of the move list holding the move shown last in a replay.
+    fn current_move_line(&self) -> Option<usize> {
+        let last = self.session.replay_position()?.checked_sub(1)?;
+        let black_first = self.session.start().0.move_turn == Color::Black;
+        Some((last + black_first as usize) / 2)
+    }
+
+    fn new_game(&mut self) {
+        self.session.new_game();
+        self.selected_piece = Position { row: 4, col: 4 };
+        self.pending_promotion = None;
+        self.history_scroll = 0;
+    }
+
+    /// Tell the opponent we are leaving and wait for the connection to close.
+    fn quit(&mut self) {
+        self.session.quit();
+        if let Some(thread) = self.network_thread.take() {
+            network::hang_up(thread);
+        }
+    }
+
+    /// Black's pieces are at the bottom when playing Black, unless the view is flipped.
+    fn black_at_bottom(&self) -> bool {
+        (self.session.my_color() == Color::Black) != self.flipped
+    }
+
+    /// Top left corner of a square on screen.
+    fn square_origin(&self, pos: Position) -> (f32, f32) {
+        let (screen_row, screen_col) = if self.black_at_bottom() {
+            (pos.row, 7 - pos.col)
+        } else {
+            (7 - pos.row, pos.col)
         };
+        (BOARD_X + SQUARE_SIZE * screen_col as f32, SQUARE_SIZE * screen_row as f32)
+    }
 
-        Ok(MainState {
-            board,
-            selected_piece: Position { row: 4, col: 4 },
-            net_writer: None,
-            net_reader: None,
-            network_mode,
-            my_color,
+    /// Square under a screen point, None outside the board.
+    fn square_at(&self, x: f32, y: f32) -> Option<Position> {
+        if x < BOARD_X || y < 0.0 {
+            return None;
+        }
+        let screen_col = ((x - BOARD_X) / SQUARE_SIZE) as i8;
+        let screen_row = (y / SQUARE_SIZE) as i8;
+        if screen_col > 7 || screen_row > 7 {
+            return None;
+        }
+        Some(if self.black_at_bottom() {
+            Position::new(screen_row, 7 - screen_col)
+        } else {
+            Position::new(7 - screen_row, screen_col)
         })
     }
+
+    /// Where the picker options go: the promotion square and the three squares
+    /// below it (above it for Black), so the picker never leaves the board.
+    fn promotion_choices(&self) -> Vec<(PieceType, graphics::Rect)> {
+        let Some((_, to)) = self.pending_promotion else { return Vec::new() };
+        let step = if to.row == 7 { -1 } else { 1 };
+        PROMOTION_PIECES
+            .iter()
+            .enumerate()
+            .map(|(i, &piece_type)| {
+                let (x, y) = self.square_origin(Position::new(to.row + step * i as i8, to.col));
+                (piece_type, graphics::Rect::new(x, y, 75.0, 75.0))
+            })
+            .collect()
+    }
+
+    fn promote(&mut self, piece_type: PieceType) {
+        let Some((from, to)) = self.pending_promotion.take() else { return };
+        match self.session.play_move(from, to, Some(piece_type)) {
+            Ok(_) => println!("Promoted pawn at {:?} -> {:?} to {:?}", from, to, piece_type),
+            Err(e) => println!("Promotion failed: {}", e),
+        }
+    }
+
+    /// Keep up with the session: new moves scroll the list and drop a half made promotion,
+    /// and the targets of the selected piece are redone whenever something changed.
+    fn follow_session(&mut self) {
+        let moves = self.session.moves().len();
+        if moves != self.moves_seen {
+            self.moves_seen = moves;
+            self.pending_promotion = None;
+            self.scroll_to_latest();
+        }
+        if !self.session.can_move() {
+            self.pending_promotion = None;
+        }
+
+        let key = (self.selected_piece, moves, self.session.can_move());
+        if self.targets_for == Some(key) {
+            return;
+        }
+        self.targets_for = Some(key);
+        self.targets = if self.replay.is_none() {
+            self.session.legal_targets(self.selected_piece)
+        } else {
+            Vec::new()
+        };
+    }
+
+    fn scroll_to_latest(&mut self) {
+        self.history_scroll = self.session.move_lines().len().saturating_sub(PANEL_LINES);
+    }
+
+    /// Undo and redo are for games on one machine, not for looking at a saved one.
+    fn can_undo(&self) -> bool {
+        self.session.can_undo() && self.replay.is_none()
+    }
+
+    fn undo(&mut self) {
+        if self.can_undo() {
+            self.session.undo();
+        }
+    }
+
+    fn redo(&mut self) {
+        if self.can_undo() {
+            self.session.redo();
+        }
+    }
+}
+
+/// Letter used to draw each piece ("k" is the knight, "K" the king).
+fn piece_glyph(piece_type: PieceType) -> &'static str {
+    match piece_type {
+        PieceType::Pawn => "p",
+        PieceType::Bishop => "B",
+        PieceType::Rook => "R",
+        PieceType::Knight => "k",
+        PieceType::Queen => "Q",
+        PieceType::King => "K",
+    }
+}
+
+/// Draws a piece on the 75x75 square with top left corner (x, y).
+fn draw_piece(
+    canvas: &mut graphics::Canvas,
+    piece_type: PieceType,
+    color: Color,
+    x: f32,
+    y: f32,
+    outline: graphics::Color,
+) {
+    let glyph = piece_glyph(piece_type);
+    let draw_piece_outer = graphics::Text::new(
+        TextFragment::new(glyph)
+            .scale(PxScale::from(65.0))
+            .color(outline),
+    );
+
+    canvas.draw(&draw_piece_outer, Vec2::new(x + 5.0, y + 5.0));
+
+    let draw_piece = graphics::Text::new(
+        TextFragment::new(glyph).scale(PxScale::from(57.0)).color(
+            if color == Color::White {
+                graphics::Color::WHITE
+            } else {
+                graphics::Color::BLACK
+            },
+        ),
+    );
+
+    canvas.draw(&draw_piece, Vec2::new(x + 7.0, y + 9.0));
+}
+
+/// Dark box across the middle of the board with a title and a smaller line of text.
+fn draw_banner(
+    ctx: &mut Context,
+    canvas: &mut graphics::Canvas,
+    title: &str,
+    title_color: graphics::Color,
+    detail: &str,
+) -> GameResult {
+    let banner = graphics::Mesh::new_rectangle(
+        ctx,
+        graphics::DrawMode::fill(),
+        graphics::Rect::new(100.0, 255.0, 600.0, 90.0),
+        graphics::Color::from([0.0, 0.0, 0.0, 0.85]),
+    )?;
+    canvas.draw(&banner, Vec2::ZERO);
+
+    let title = graphics::Text::new(
+        TextFragment::new(title)
+            .color(title_color)
+            .scale(PxScale::from(36.0)),
+    );
+    canvas.draw(&title, Vec2::new(115.0, 265.0));
+
+    let mut detail = graphics::Text::new(
+        TextFragment::new(detail)
+            .color(graphics::Color::WHITE)
+            .scale(PxScale::from(18.0)),
+    );
+    detail.set_bounds(Vec2::new(570.0, 40.0));
+    canvas.draw(&detail, Vec2::new(115.0, 305.0));
+    Ok(())
+}
+
+fn draw_button(ctx: &mut Context, canvas: &mut graphics::Canvas, rect: graphics::Rect, label: &str) -> GameResult {
+    let mesh = graphics::Mesh::new_rectangle(ctx, graphics::DrawMode::fill(), rect, graphics::Color::WHITE)?;
+    canvas.draw(&mesh, Vec2::ZERO);
+
+    let text = graphics::Text::new(
+        TextFragment::new(label)
+            .color(graphics::Color::BLACK)
+            .scale(PxScale::from(28.0)),
+    );
+    let size = text.measure(ctx)?;
+    canvas.draw(&text, Vec2::new(rect.x + (rect.w - size.x) / 2.0, rect.y + (rect.h - size.y) / 2.0));
+    Ok(())
+}
+
+/// Result screen on top of the board with "new game" and "quit".
+fn draw_result_overlay(ctx: &mut Context, canvas: &mut graphics::Canvas, over: &GameOver, can_restart: bool) -> GameResult {
+    let panel = graphics::Mesh::new_rectangle(
+        ctx,
+        graphics::DrawMode::fill(),
+        OVERLAY,
+        graphics::Color::from([0.0, 0.0, 0.0, 0.85]),
+    )?;
+    canvas.draw(&panel, Vec2::ZERO);
+
+    let mut title = graphics::Text::new(
+        TextFragment::new(over.reason.as_str())
+            .color(graphics::Color::WHITE)
+            .scale(PxScale::from(30.0)),
+    );
+    title.set_bounds(Vec2::new(OVERLAY.w - 40.0, 70.0));
+    canvas.draw(&title, Vec2::new(OVERLAY.x + 20.0, OVERLAY.y + 20.0));
+
+    let result = graphics::Text::new(
+        TextFragment::new(format!("Result: {}", over.result))
+            .color(graphics::Color::WHITE)
+            .scale(PxScale::from(22.0)),
+    );
+    canvas.draw(&result, Vec2::new(OVERLAY.x + 20.0, OVERLAY.y + 80.0));
+
+    if can_restart {
+        draw_button(ctx, canvas, NEW_GAME_BUTTON, "New game")?;
+    }
+    draw_button(ctx, canvas, QUIT_BUTTON, "Quit")?;
+    Ok(())
+}
+
+/// A question from the opponent with accept and decline, on top of the board like
+/// the result screen.
+fn draw_prompt(ctx: &mut Context, canvas: &mut graphics::Canvas, title: &str, detail: &str) -> GameResult {
+    let panel = graphics::Mesh::new_rectangle(
+        ctx,
+        graphics::DrawMode::fill(),
+        OVERLAY,
+        graphics::Color::from([0.0, 0.0, 0.0, 0.85]),
+    )?;
+    canvas.draw(&panel, Vec2::ZERO);
+
+    let title = graphics::Text::new(
+        TextFragment::new(title)
+            .color(graphics::Color::WHITE)
+            .scale(PxScale::from(30.0)),
+    );
+    canvas.draw(&title, Vec2::new(OVERLAY.x + 20.0, OVERLAY.y + 20.0));
+
+    let detail = graphics::Text::new(
+        TextFragment::new(detail)
+            .color(graphics::Color::WHITE)
+            .scale(PxScale::from(22.0)),
+    );
+    canvas.draw(&detail, Vec2::new(OVERLAY.x + 20.0, OVERLAY.y + 80.0));
+
+    draw_button(ctx, canvas, ACCEPT_BUTTON, "Accept")?;
+    draw_button(ctx, canvas, DECLINE_BUTTON, "Decline")?;
+    Ok(())
 }
 
 impl event::EventHandler<ggez::GameError> for MainState {
-    fn update(&mut self, _ctx: &mut ggez::Context) -> ggez::GameResult {
-        if let Some(rx) = &self.net_reader {
-            while let Ok(msg) = rx.try_recv() {
-                match msg {
-                    ProtocolMsg::Move(m) => {
-                        if let Err(e) = crate::helper::apply_message_to_board(&mut self.board, &m) {
-                            eprintln!("Failed to apply network move: {}", e);
-                        } else {
-                            println!("Opponent move applied: {}", m.move_str);
-                        }
-                    }
-                    ProtocolMsg::Quit(q) => {
-                        println!("Opponent quit: {}", q.reason);
-                    }
-                }
-            }
+    fn update(&mut self, ctx: &mut ggez::Context) -> ggez::GameResult {
+        if self.interrupted.load(Ordering::SeqCst) {
+            ctx.request_quit();
+        }
+        if self.replay.is_none() {
+            self.session.poll();
         }
+        self.follow_session();
         Ok(())
     }
+
+    /// Closing the window, the Quit buttons and Ctrl-C all end up here.
+    fn quit_event(&mut self, _ctx: &mut Context) -> GameResult<bool> {
+        self.quit();
+        Ok(false)
+    }
+
     fn draw(&mut self, ctx: &mut Context) -> GameResult {
         let cerise = graphics::Color::from([0.87, 0.19, 0.39, 1.0]); // cerise by ChatGPT
 
         let mut canvas = graphics::Canvas::from_frame(ctx, cerise);
 
+        // only the side to move can be in check
+        let checked_king = rules::find_king(self.session.board(), self.session.board().move_turn)
+            .filter(|_| rules::is_in_check(self.session.board(), self.session.board().move_turn));
+
         for row in 0..8 {
             for col in 0..8 {
-                // dbg!(col);
-                // dbg!(75 * col);
-                // dbg!(53 + (75 * col));
-                let x: f32 = 100.0 + (75.0 * col as f32);
-                let y: f32 = 75.0 * (row as f32);
+                let pos = Position::new(row, col);
+                let (x, y) = self.square_origin(pos);
 
                 let square = graphics::Mesh::new_rectangle(
                     ctx,
@@ -85,10 +419,10 @@ impl event::EventHandler<ggez::GameError> for MainState {
                     graphics::Rect {
                         x: 0.0,
                         y: 0.0,
-                        w: 75.0,
-                        h: 75.0,
+                        w: SQUARE_SIZE,
+                        h: SQUARE_SIZE,
                     },
-                    if (row + col) % 2 == 0 {
+                    if (row + col) % 2 == 1 { // a1 is dark
                         graphics::Color::WHITE
                     } else {
                         graphics::Color::BLACK
@@ -97,152 +431,88 @@ impl event::EventHandler<ggez::GameError> for MainState {
 
                 canvas.draw(&square, Vec2::new(x, y));
 
-                let pos = Position {
-                    row: 7 as i8 - row,
-                    col,
-                };
-                match self.board.get(pos) {
-                    Some(piece) => {
-                        let draw_color = match piece.color {
-                            hermanha_chess::Color::White => "w",
-                            hermanha_chess::Color::Black => "b",
-                        };
-
-                        match piece.piece_type {
-                            hermanha_chess::PieceType::Pawn => {
-                                let draw_piece_outer = graphics::Text::new(
-                                    TextFragment::new("p")
-                                        .scale(PxScale::from(65.0))
-                                        .color(cerise),
-                                );
-
-                                canvas.draw(&draw_piece_outer, Vec2::new(x + 5.0, y + 5.0));
-
-                                let draw_piece = graphics::Text::new(
-                                    TextFragment::new("p").scale(PxScale::from(57.0)).color(
-                                        if draw_color == "w" {
-                                            graphics::Color::WHITE
-                                        } else {
-                                            graphics::Color::BLACK
-                                        },
-                                    ),
-                                );
-
-                                canvas.draw(&draw_piece, Vec2::new(x + 7.0, y + 9.0));
-                            }
-                            hermanha_chess::PieceType::Bishop => {
-                                let draw_piece_outer = graphics::Text::new(
-                                    TextFragment::new("B")
-                                        .scale(PxScale::from(65.0))
-                                        .color(cerise),
-                                );
-
-                                canvas.draw(&draw_piece_outer, Vec2::new(x + 5.0, y + 5.0));
-
-                                let draw_piece = graphics::Text::new(
-                                    TextFragment::new("B").scale(PxScale::from(57.0)).color(
-                                        if draw_color == "w" {
-                                            graphics::Color::WHITE
-                                        } else {
-                                            graphics::Color::BLACK
-                                        },
-                                    ),
-                                );
-
-                                canvas.draw(&draw_piece, Vec2::new(x + 7.0, y + 9.0));
-                            }
-                            hermanha_chess::PieceType::Rook => {
-                                let draw_piece_outer = graphics::Text::new(
-                                    TextFragment::new("R")
-                                        .scale(PxScale::from(65.0))
-                                        .color(cerise),
-                                );
-
-                                canvas.draw(&draw_piece_outer, Vec2::new(x + 5.0, y + 5.0));
-
-                                let draw_piece = graphics::Text::new(
-                                    TextFragment::new("R").scale(PxScale::from(57.0)).color(
-                                        if draw_color == "w" {
-                                            graphics::Color::WHITE
-                                        } else {
-                                            graphics::Color::BLACK
-                                        },
-                                    ),
-                                );
-
-                                canvas.draw(&draw_piece, Vec2::new(x + 7.0, y + 9.0));
-                            }
-                            hermanha_chess::PieceType::Knight => {
-                                let draw_piece_outer = graphics::Text::new(
-                                    TextFragment::new("k")
-                                        .scale(PxScale::from(65.0))
-                                        .color(cerise),
-                                );
-
-                                canvas.draw(&draw_piece_outer, Vec2::new(x + 5.0, y + 5.0));
-
-                                let draw_piece = graphics::Text::new(
-                                    TextFragment::new("k").scale(PxScale::from(57.0)).color(
-                                        if draw_color == "w" {
-                                            graphics::Color::WHITE
-                                        } else {
-                                            graphics::Color::BLACK
-                                        },
-                                    ),
-                                );
-
-                                canvas.draw(&draw_piece, Vec2::new(x + 7.0, y + 9.0));
-                            }
-                            hermanha_chess::PieceType::Queen => {
-                                let draw_piece_outer = graphics::Text::new(
-                                    TextFragment::new("Q")
-                                        .scale(PxScale::from(65.0))
-                                        .color(cerise),
-                                );
-
-                                canvas.draw(&draw_piece_outer, Vec2::new(x + 5.0, y + 5.0));
-
-                                let draw_piece = graphics::Text::new(
-                                    TextFragment::new("Q").scale(PxScale::from(57.0)).color(
-                                        if draw_color == "w" {
-                                            graphics::Color::WHITE
-                                        } else {
-                                            graphics::Color::BLACK
-                                        },
-                                    ),
-                                );
-
-                                canvas.draw(&draw_piece, Vec2::new(x + 7.0, y + 9.0));
-                            }
-                            hermanha_chess::PieceType::King => {
-                                let draw_piece_outer = graphics::Text::new(
-                                    TextFragment::new("K")
-                                        .scale(PxScale::from(65.0))
-                                        .color(cerise),
-                                );
-
-                                canvas.draw(&draw_piece_outer, Vec2::new(x + 5.0, y + 5.0));
-
-                                let draw_piece = graphics::Text::new(
-                                    TextFragment::new("K").scale(PxScale::from(57.0)).color(
-                                        if draw_color == "w" {
-                                            graphics::Color::WHITE
-                                        } else {
-                                            graphics::Color::BLACK
-                                        },
-                                    ),
-                                );
-
-                                canvas.draw(&draw_piece, Vec2::new(x + 7.0, y + 9.0));
-                            }
-                        }
-                    }
-                    None => {}
+                if let Some((from, to)) = self.session.last_move()
+                    && (pos == from || pos == to)
+                {
+                    let tint = graphics::Mesh::new_rectangle(
+                        ctx,
+                        graphics::DrawMode::fill(),
+                        graphics::Rect::new(0.0, 0.0, SQUARE_SIZE, SQUARE_SIZE),
+                        graphics::Color::from([0.6, 0.75, 0.35, 0.7]),
+                    )?;
+                    canvas.draw(&tint, Vec2::new(x, y));
+                }
+
+                if Some(pos) == checked_king {
+                    let check = graphics::Mesh::new_rectangle(
+                        ctx,
+                        graphics::DrawMode::fill(),
+                        graphics::Rect::new(0.0, 0.0, SQUARE_SIZE, SQUARE_SIZE),
+                        graphics::Color::from([0.9, 0.1, 0.1, 0.85]),
+                    )?;
+                    canvas.draw(&check, Vec2::new(x, y));
+                }
+
+                if pos == self.selected_piece && !self.targets.is_empty() {
+                    let selected = graphics::Mesh::new_rectangle(
+                        ctx,
+                        graphics::DrawMode::fill(),
+                        graphics::Rect::new(0.0, 0.0, SQUARE_SIZE, SQUARE_SIZE),
+                        graphics::Color::from([0.95, 0.8, 0.2, 0.8]),
+                    )?;
+                    canvas.draw(&selected, Vec2::new(x, y));
+                }
+
+                if let Some(piece) = self.session.board().get(pos) {
+                    draw_piece(&mut canvas, piece.piece_type, piece.color, x, y, cerise);
                 }
             }
         }
+
+        // where the selected piece can go: a dot on empty squares, a ring around captures
+        let marker = graphics::Color::from([0.2, 0.7, 0.3, 0.8]);
+        for &target in &self.targets {
+            let (x, y) = self.square_origin(target);
+            let center = Vec2::new(x + SQUARE_SIZE / 2.0, y + SQUARE_SIZE / 2.0);
+            let mesh = if self.session.board().get(target).is_some() {
+                graphics::Mesh::new_circle(ctx, graphics::DrawMode::stroke(5.0), center, SQUARE_SIZE / 2.0 - 4.0, 0.5, marker)?
+            } else {
+                graphics::Mesh::new_circle(ctx, graphics::DrawMode::fill(), center, SQUARE_SIZE / 8.0, 0.5, marker)?
+            };
+            canvas.draw(&mesh, Vec2::ZERO);
+        }
+
+        // coordinates: ranks left of the board, files below it
+        for i in 0..8 {
+            let (_, y) = self.square_origin(Position::new(i, 0));
+            let rank = graphics::Text::new(TextFragment::new((i + 1).to_string())
+                .color(graphics::Color::WHITE)
+                .scale(PxScale::from(20.0)));
+            canvas.draw(&rank, Vec2::new(BOARD_X - 16.0, y + 28.0));
+
+            let (x, _) = self.square_origin(Position::new(0, i));
+            let file = graphics::Text::new(TextFragment::new(((b'a' + i as u8) as char).to_string())
+                .color(graphics::Color::WHITE)
+                .scale(PxScale::from(20.0)));
+            canvas.draw(&file, Vec2::new(x + 32.0, 8.0 * SQUARE_SIZE + 4.0));
+        }
+
+        if self.pending_promotion.is_some() {
+            let color = self.session.board().move_turn;
+            for (piece_type, rect) in self.promotion_choices() {
+                let option = graphics::Mesh::new_rectangle(
+                    ctx,
+                    graphics::DrawMode::fill(),
+                    rect,
+                    graphics::Color::from([0.6, 0.6, 0.6, 1.0]),
+                )?;
+                canvas.draw(&option, Vec2::ZERO);
+                draw_piece(&mut canvas, piece_type, color, rect.x, rect.y, cerise);
+            }
+        }
+
         // debug: server or client mode
-        if let Some(mode) = &self.network_mode {
+        if let Some(mode) = self.session.network_mode() {
             let mode_text = graphics::Text::new(TextFragment::new(format!("Mode: {}", mode))
                 .color(if mode == "server" {
                     graphics::Color::GREEN
@@ -251,6 +521,92 @@ impl event::EventHandler<ggez::GameError> for MainState {
                 })
                 .scale(PxScale::from(24.0)));
             canvas.draw(&mode_text, Vec2::new(10.0, 10.0));
+
+            let side = if self.session.negotiating() { "?" } else { status::color_name(self.session.my_color()) };
+            let side_text = graphics::Text::new(TextFragment::new(format!("You: {}", side))
+                .color(graphics::Color::WHITE)
+                .scale(PxScale::from(18.0)));
+            canvas.draw(&side_text, Vec2::new(10.0, 60.0));
+        }
+        if let Some(level) = self.session.ai() {
+            let thinking = if self.session.thinking() { "\nthinking..." } else { "" };
+            let ai_text = graphics::Text::new(TextFragment::new(format!("Computer\n{}{}", level.as_str(), thinking))
+                .color(graphics::Color::WHITE)
+                .scale(PxScale::from(18.0)));
+            canvas.draw(&ai_text, Vec2::new(10.0, 55.0));
+        }
+
+        // clocks left of the board, each next to its side
+        if let Some(clock) = self.session.clock() {
+            let now = Instant::now();
+            let bottom = if self.black_at_bottom() { Color::Black } else { Color::White };
+            for (color, y) in [(rules::opponent(bottom), 110.0), (bottom, 8.0 * SQUARE_SIZE - 150.0)] {
+                let left = clock.remaining(color, now);
+                let face = if left.is_zero() {
+                    graphics::Color::RED
+                } else if clock.running() == Some(color) {
+                    graphics::Color::YELLOW
+                } else {
+                    graphics::Color::WHITE
+                };
+                let text = graphics::Text::new(TextFragment::new(clock::format_time(left))
+                    .color(face)
+                    .scale(PxScale::from(24.0)));
+                canvas.draw(&text, Vec2::new(10.0, y));
+            }
+        }
+
+        // move list, scrolled with the mouse wheel
+        let lines = self.session.move_lines();
+        let first = self.history_scroll.min(lines.len().saturating_sub(PANEL_LINES));
+        let title = match self.session.replay_position() {
+            Some(at) => format!("Move {} of {}", at, self.session.moves().len()),
+            None => "Moves".to_string(),
+        };
+        let title = graphics::Text::new(TextFragment::new(title)
+            .color(graphics::Color::WHITE)
+            .scale(PxScale::from(22.0)));
+        canvas.draw(&title, Vec2::new(PANEL_X, 10.0));
+        let current = self.current_move_line();
+        for (i, line) in lines.iter().enumerate().skip(first).take(PANEL_LINES) {
+            let text = graphics::Text::new(TextFragment::new(line.as_str())
+                .color(if Some(i) == current { graphics::Color::YELLOW } else { graphics::Color::WHITE })
+                .scale(PxScale::from(18.0)));
+            canvas.draw(&text, Vec2::new(PANEL_X, 40.0 + (i - first) as f32 * PANEL_LINE));
+        }
+        if self.can_undo() {
+            draw_button(ctx, &mut canvas, UNDO_BUTTON, "Undo")?;
+            draw_button(ctx, &mut canvas, REDO_BUTTON, "Redo")?;
+        } else if self.session.network_game_on() {
+            let label = if self.session.takeback_sent().is_some() { "Asked..." } else { "Takeback" };
+            draw_button(ctx, &mut canvas, TAKEBACK_BUTTON, label)?;
+            let label = match (self.resign_armed, self.session.can_abort()) {
+                (true, _) => "Sure?",
+                (false, true) => "Abort",
+                (false, false) => "Resign",
+            };
+            draw_button(ctx, &mut canvas, RESIGN_BUTTON, label)?;
+            draw_button(ctx, &mut canvas, DRAW_BUTTON, if self.session.draw_offered() { "Offered" } else { "Draw" })?;
+        }
+        if let Some(game) = &self.replay {
+            let players = format!("{} - {}  {}", game.white, game.black, pgn::result_tag(&game.result));
+            let mut players = graphics::Text::new(TextFragment::new(players)
+                .color(graphics::Color::WHITE)
+                .scale(PxScale::from(16.0)));
+            players.set_bounds(Vec2::new(200.0, 20.0));
+            canvas.draw(&players, Vec2::new(PANEL_X, 8.0 * SQUARE_SIZE + 8.0));
+        }
+
+        if let Some(why) = self.session.desync() {
+            draw_banner(ctx, &mut canvas, "Desync - game stopped", cerise, why)?;
+        } else if let Some(over) = self.session.game_over() {
+            // chesstp has no rematch message, so a network game can only be quit
+            draw_result_overlay(ctx, &mut canvas, over, self.session.network_mode().is_none())?;
+        } else if let Some(request) = self.session.takeback_prompt() {
+            let detail = if request.plies == 1 { "Their last move" } else { "Their last move and your answer" };
+            draw_prompt(ctx, &mut canvas, "Opponent asks for a takeback", detail)?;
+        } else if self.session.draw_prompt() {
+            draw_prompt(ctx, &mut canvas, "Opponent offers a draw", "Accept to end the game 1/2-1/2")?;
         }
 
         // QUIT button
@@ -275,75 +631,157 @@ impl event::EventHandler<ggez::GameError> for MainState {
         Ok(())
     }
 
+    fn key_down_event(&mut self, _ctx: &mut Context, input: KeyInput, _repeated: bool) -> GameResult {
+        match input.keycode {
+            Some(KeyCode::F) => self.flipped = !self.flipped,
+            Some(KeyCode::S) if self.replay.is_none() => self.session.save_pgn(None),
+            Some(KeyCode::Z) if input.mods.contains(KeyMods::CTRL | KeyMods::SHIFT) => self.redo(),
+            Some(KeyCode::Z) if input.mods.contains(KeyMods::CTRL) && self.session.network_mode().is_some() => {
+                self.session.request_takeback()
+            }
+            Some(KeyCode::Z) if input.mods.contains(KeyMods::CTRL) => self.undo(),
+            Some(KeyCode::Y) if input.mods.contains(KeyMods::CTRL) => self.redo(),
+            Some(KeyCode::Left) => self.step_replay(self.session.replay_position().map_or(0, |at| at.saturating_sub(1))),
+            Some(KeyCode::Right) => self.step_replay(self.session.replay_position().map_or(0, |at| at + 1)),
+            Some(KeyCode::Home) => self.step_replay(0),
+            Some(KeyCode::End) => self.step_replay(usize::MAX),
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn mouse_wheel_event(&mut self, _ctx: &mut Context, _x: f32, y: f32) -> GameResult {
+        let last = self.session.move_lines().len().saturating_sub(PANEL_LINES);
+        if y > 0.0 {
+            self.history_scroll = self.history_scroll.min(last).saturating_sub(1);
+        } else if y < 0.0 {
+            self.history_scroll = (self.history_scroll + 1).min(last);
+        }
+        Ok(())
+    }
+
     fn mouse_button_down_event(
         &mut self,
-        _ctx: &mut Context,
+        ctx: &mut Context,
         _button: event::MouseButton,
         x: f32,
         y: f32,
     ) -> Result<(), ggez::GameError> {
-        let row: f32 = y / 75.0;
-        let col: f32 = (x - 100.0) / 75.0;
-        let clicked_pos = Position {
-            row: 7 - row as i8,
-            col: col as i8,
-        };
-
         //quite button
-        if x >= 10.0 && x <= 50.0 && y >= 10.0 && y <= 50.0 {
+        if (10.0..=50.0).contains(&x) && (10.0..=50.0).contains(&y) {
             println!("QUIT button clicked");
+            ctx.request_quit();
+        }
 
-            if let Some(tx) = &self.net_writer {
-                let quit_msg = crate::protocol::ProtocolMsg::Quit(crate::protocol::QuitMsg {
-                    reason: "User exited".to_string(),
-                });
-                if let Err(e) = tx.send(quit_msg) {
-                    eprintln!("Failed to send QUIT message: {}", e);
+        if self.replay.is_some() {
+            return Ok(());
+        }
+
+        // before the result screen, so a finished game can be taken back
+        if self.can_undo() && UNDO_BUTTON.contains([x, y]) {
+            self.undo();
+            return Ok(());
+        }
+        if self.can_undo() && REDO_BUTTON.contains([x, y]) {
+            self.redo();
+            return Ok(());
+        }
+
+        if self.session.game_over().is_some() && self.session.desync().is_none() {
+            if QUIT_BUTTON.contains([x, y]) {
+                println!("Quit chosen on result screen");
+                ctx.request_quit();
+            } else if NEW_GAME_BUTTON.contains([x, y]) && self.session.network_mode().is_none() {
+                self.new_game();
+            }
+            return Ok(());
+        }
+
+        if self.session.desync().is_some() {
+            return Ok(());
+        }
+
+        if self.session.takeback_prompt().is_some() {
+            if ACCEPT_BUTTON.contains([x, y]) {
+                self.session.answer_takeback(true);
+            } else if DECLINE_BUTTON.contains([x, y]) {
+                self.session.answer_takeback(false);
+            }
+            return Ok(());
+        }
+        if self.session.draw_prompt() {
+            if ACCEPT_BUTTON.contains([x, y]) {
+                self.session.answer_draw(true);
+            } else if DECLINE_BUTTON.contains([x, y]) {
+                self.session.answer_draw(false);
+            }
+            return Ok(());
+        }
+        let resign_armed = std::mem::take(&mut self.resign_armed); // any other click disarms it
+        if self.session.network_game_on() {
+            if TAKEBACK_BUTTON.contains([x, y]) {
+                self.session.request_takeback();
+                return Ok(());
+            }
+            if RESIGN_BUTTON.contains([x, y]) {
+                if resign_armed {
+                    self.session.resign();
+                } else {
+                    self.resign_armed = true;
                 }
+                return Ok(());
+            }
+            if DRAW_BUTTON.contains([x, y]) {
+                self.session.offer_draw();
+                return Ok(());
             }
-            thread::sleep(Duration::from_millis(100)); // give time for message to be sent
-            std::process::exit(0);
         }
 
-        if self.board.move_turn != self.my_color && self.network_mode.is_some() {
+        // the picker is modal: pick a piece or click anywhere else to take the move back
+        if self.pending_promotion.is_some() {
+            let picked = self
+                .promotion_choices()
+                .into_iter()
+                .find(|(_, rect)| rect.contains([x, y]));
+            match picked {
+                Some((piece_type, _)) => self.promote(piece_type),
+                None => {
+                    println!("Promotion cancelled");
+                    self.pending_promotion = None;
+                }
+            }
+            return Ok(());
+        }
+
+        if self.session.negotiating() {
+            println!("Waiting for the opponent to agree on colours.");
+            return Ok(());
+        }
+
+        if self.session.takeback_sent().is_some() {
+            println!("Waiting for the opponent to answer the takeback.");
+            return Ok(());
+        }
+
+        let Some(clicked_pos) = self.square_at(x, y) else {
+            return Ok(());
+        };
+
+        if !self.session.my_turn() {
             println!("Not your turn! Waiting for opponent.");
             self.selected_piece = clicked_pos;
             return Ok(());
         }
 
-        if let Some(_piece) = self.board.get(self.selected_piece) {
-            match self.board.move_piece(self.selected_piece, clicked_pos, None) {
+        if let Some(_piece) = self.session.board().get(self.selected_piece) {
+            let from = self.selected_piece;
+            match self.session.play_move(from, clicked_pos, None) {
                 Ok(MoveOk::NeedsPromotion) => {
-                    match self.board.move_piece(self.selected_piece, clicked_pos, Some(PieceType::Queen)) { //note: only support queen promotion 
-                        Ok(_) => {
-                            println!("Promoted pawn at {:?} -> {:?} to Queen", self.selected_piece, clicked_pos);
-
-                            if let Some(tx) = &self.net_writer {
-                                let msg = board_move_to_message(
-                                    self.selected_piece,
-                                    clicked_pos,
-                                    Some(PieceType::Queen),
-                                    &self.board,
-                                );
-                                if let Err(e) = tx.send(ProtocolMsg::Move(msg)) {
-                                    eprintln!("Failed to send promotion move: {}", e);
-                                }
-                            }
-                        }
-                        Err(e) => println!("Promotion failed: {:?}", e),
-                    }
-                }
-                Ok(_) => {
-                    println!("Move applied locally: {:?} -> {:?}", self.selected_piece, clicked_pos);
-
-                    if let Some(tx) = &self.net_writer {
-                        let msg = board_move_to_message(self.selected_piece, clicked_pos, None, &self.board);
-                        if let Err(e) = tx.send(ProtocolMsg::Move(msg)) {
-                            eprintln!("Failed to send move over network: {}", e);
-                        }
-                    }
+                    println!("Pick a piece to promote to");
+                    self.pending_promotion = Some((from, clicked_pos));
                 }
-                Err(e) => println!("Failed to move piece: {:?}", e),
+                Ok(_) => {}
+                Err(e) => println!("Failed to move piece: {}", e),
             }
         }
 
@@ -356,44 +794,39 @@ impl event::EventHandler<ggez::GameError> for MainState {
 
 // https://doc.rust-lang.org/beta/std/env/fn.args.html
 pub fn main() -> GameResult {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let options = match cli::parse_args(&args) {
+        Ok(options) => options,
+        Err(e @ cli::CliError::Help) => {
+            println!("{}", e);
+            return Ok(());
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
 
-    let mut network_mode = None;
-    if args.len() > 1 {
-        network_mode = Some(args[1].clone()); 
+    if options.tui {
+        if let Err(e) = tui::run(&options) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return Ok(());
     }
 
-    let cb = ggez::ContextBuilder::new("eahla_chess_game_gui", "ggez");
-    let (ctx, event_loop) = cb.build()?;
-    let mut state = MainState::new(network_mode.clone())?;
-
-    if let Some(mode) = network_mode {
-        let (tx_to_network, rx_from_gui) = std::sync::mpsc::channel::<ProtocolMsg>();
-        let (tx_to_gui, rx_from_network) = std::sync::mpsc::channel::<ProtocolMsg>();
-
-        state.net_writer = Some(tx_to_network);  // GUI sends local moves to network
-        state.net_reader = Some(rx_from_network); // GUI receives moves from network
-
-        let tx_clone = tx_to_gui.clone();
-
-        match mode.as_str() {
-            "client" => {
-                let rx = rx_from_gui;
-                std::thread::spawn(move || {
-                    network::start_client_with_channel("127.0.0.1:6969", rx, tx_clone).unwrap();
-                });
-            }
-            "server" => {
-                let rx = rx_from_gui;
-                std::thread::spawn(move || {
-                    network::start_server_with_channel("127.0.0.1:6969", rx, tx_clone).unwrap();
-                });
-            }
-            _ => {
-                eprintln!("Unknown argument: {}. Use 'client' or 'server'.", mode);
-            }
-        }
+    let session = GameSession::new(&options).map_err(ggez::GameError::CustomError)?;
 
+    let cb = ggez::ContextBuilder::new("eahla_chess_game_gui", "ggez")
+        .window_mode(ggez::conf::WindowMode::default().dimensions(PANEL_X + 220.0, 8.0 * SQUARE_SIZE + 30.0)); // room for the file letters and the move list
+    let (ctx, event_loop) = cb.build()?;
+    let mut state = MainState::new(session, network::ctrl_c_flag());
+    if let Mode::Replay { file } = &options.mode {
+        state.load_replay(file)?;
+    }
+    if let Some((channels, thread)) = network::spawn_opponent(&options.mode, options.start_fen.as_deref()) {
+        state.session.connect(channels);
+        state.network_thread = Some(thread);
     }
     event::run(ctx, event_loop, state)
 
@@ -1,69 +1,323 @@
-use std::io::{Read, Write};
-use std::net::{TcpStream, TcpListener};
-use std::sync::mpsc::{Sender, Receiver};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpStream, TcpListener};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender, Receiver};
+use std::sync::Arc;
 use std::thread;
-use crate::protocol::ProtocolMsg;
+use std::time::{Duration, Instant};
+use crate::cli::Mode;
+use crate::protocol::{ProtocolError, ProtocolMsg};
+use crate::uci;
 
-pub fn start_client_with_channel(
-    addr: &str, 
-    rx: Receiver<ProtocolMsg>, 
-    tx_to_gui: Sender<ProtocolMsg>,
-) -> std::io::Result<()> {
-    let mut stream = TcpStream::connect(addr)?;
-    let mut reader = stream.try_clone()?;
+/// Every chesstp message is exactly this many bytes on the wire.
+pub const FRAME_LEN: usize = 128;
+/// All chesstp headers start with this, used to find the next frame after garbage.
+const FRAME_MAGIC: &[u8] = b"Chess";
+/// How long a closing connection waits for the peer to close its end too.
+const CLOSE_WAIT: Duration = Duration::from_secs(1);
+/// How long a front end waits for its network thread when quitting.
+const SHUTDOWN_WAIT: Duration = Duration::from_secs(3);
 
-    println!("Connected to server at {}", addr);
-    
-    let tx_clone = tx_to_gui.clone();
-    thread::spawn(move || {
-        let mut buf = [0u8; 128];
-        while let Ok(n) = reader.read(&mut buf) {
-            if n == 0 { break; }
-            let raw = String::from_utf8_lossy(&buf[..n]);
-            println!("Raw msg received: {}", raw);
-
-            if let Some(msg) = ProtocolMsg::deserialize(&raw) {
-                tx_clone.send(msg).unwrap();
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    /// Outgoing message is not exactly one frame long, too long or too short.
+    WrongLength(usize),
+    /// Bytes that can not be the start of a frame, or a frame that is not ASCII.
+    Malformed(String),
+    /// Connection closed with only part of a frame received.
+    Truncated(usize),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::Io(e) => write!(f, "io error: {}", e),
+            FrameError::WrongLength(n) => write!(f, "frame is {} bytes, expected {}", n, FRAME_LEN),
+            FrameError::Malformed(why) => write!(f, "malformed frame: {}", why),
+            FrameError::Truncated(n) => write!(f, "connection closed after {} of {} bytes", n, FRAME_LEN),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        FrameError::Io(e)
+    }
+}
+
+/// Collects raw bytes and cuts them into 128-byte frames, no matter how TCP split them up.
+#[derive(Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        FrameDecoder { buf: Vec::new() }
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Number of bytes waiting for the rest of their frame.
+    pub fn pending(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Next complete frame, if there is one. Garbage in front of a frame is
+    /// dropped and reported once so the stream can recover on the next header.
+    pub fn next_frame(&mut self) -> Option<Result<String, FrameError>> {
+        if self.buf.is_empty() {
+            return None;
+        }
+
+        if !self.buf.starts_with(&FRAME_MAGIC[..self.buf.len().min(FRAME_MAGIC.len())]) {
+            let skip = find_magic(&self.buf).unwrap_or(self.buf.len());
+            let garbage: Vec<u8> = self.buf.drain(..skip).collect();
+            return Some(Err(FrameError::Malformed(format!(
+                "skipped {} bytes without a chesstp header: {:?}",
+                garbage.len(),
+                String::from_utf8_lossy(&garbage)
+            ))));
+        }
+
+        if self.buf.len() < FRAME_LEN {
+            return None;
+        }
+
+        let frame: Vec<u8> = self.buf.drain(..FRAME_LEN).collect();
+        if !frame.is_ascii() {
+            return Some(Err(FrameError::Malformed("frame is not ASCII".to_string())));
+        }
+        Some(Ok(String::from_utf8(frame).expect("ASCII is valid UTF-8")))
+    }
+}
+
+/// Start of the first header in `buf` that is not at index 0. A header cut off
+/// at the very end counts too, the rest of it may still be on the way.
+fn find_magic(buf: &[u8]) -> Option<usize> {
+    (1..buf.len()).find(|&i| {
+        let rest = &buf[i..];
+        let n = rest.len().min(FRAME_MAGIC.len());
+        rest[..n] == FRAME_MAGIC[..n]
+    })
+}
+
+/// Blocking frame reader on top of any byte stream (normally the TcpStream).
+pub struct FrameReader<R> {
+    inner: R,
+    decoder: FrameDecoder,
+}
+
+impl<R: Read> FrameReader<R> {
+    pub fn new(inner: R) -> Self {
+        FrameReader { inner, decoder: FrameDecoder::new() }
+    }
+
+    pub fn get_ref(&self) -> &R {
+        &self.inner
+    }
+
+    /// Reads until a whole frame is available. `Ok(None)` means the peer closed
+    /// the connection cleanly between frames.
+    pub fn read_frame(&mut self) -> Result<Option<String>, FrameError> {
+        let mut buf = [0u8; FRAME_LEN];
+        loop {
+            if let Some(frame) = self.decoder.next_frame() {
+                return frame.map(Some);
+            }
+            let n = match self.inner.read(&mut buf) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            };
+            if n == 0 {
+                let pending = self.decoder.pending();
+                self.decoder = FrameDecoder::new();
+                return if pending == 0 { Ok(None) } else { Err(FrameError::Truncated(pending)) };
             }
+            self.decoder.push(&buf[..n]);
         }
+    }
+}
+
+/// Writes one frame, refusing anything that is not exactly FRAME_LEN bytes
+/// since the peer would lose track of where the next message starts.
+pub fn write_frame<W: Write>(writer: &mut W, frame: &str) -> Result<(), FrameError> {
+    if frame.len() != FRAME_LEN {
+        return Err(FrameError::WrongLength(frame.len()));
+    }
+    writer.write_all(frame.as_bytes())?;
+    Ok(())
+}
 
-    });
+fn spawn_reader(
+    stream: TcpStream,
+    tx_to_gui: Sender<Result<ProtocolMsg, ProtocolError>>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut reader = FrameReader::new(stream);
+        loop {
+            match reader.read_frame() {
+                Ok(Some(raw)) => {
+                    println!("Raw msg received: {}", raw);
+                    let msg = ProtocolMsg::deserialize(&raw);
+                    if let Err(e) = &msg {
+                        eprintln!("Bad message from peer: {}", e);
+                    }
+                    if tx_to_gui.send(msg).is_err() {
+                        break; // GUI is gone
+                    }
+                }
+                Ok(None) => {
+                    // the peer hung up, hang up too so its reader is not left waiting
+                    let _ = reader.get_ref().shutdown(Shutdown::Write);
+                    break;
+                }
+                Err(FrameError::Io(e)) => {
+                    eprintln!("Connection error: {}", e);
+                    break;
+                }
+                Err(e) => {
+                    eprintln!("Dropped bad frame: {}", e);
+                    if let FrameError::Truncated(_) = e {
+                        break;
+                    }
+                }
+            }
+        }
+    })
+}
 
-    // Main send loop: receives moves from GUI and sends over TCP
+fn send_loop(stream: &mut TcpStream, rx: Receiver<ProtocolMsg>) -> io::Result<()> {
+    // Main send loop: receives moves from GUI and sends over TCP, until the GUI drops its Sender
     while let Ok(msg) = rx.recv() {
-                let serialized = msg.serialize();
-        stream.write_all(serialized.as_bytes())?;
+        match write_frame(stream, &msg.serialize()) {
+            Ok(()) => {}
+            Err(FrameError::Io(e)) if e.kind() == io::ErrorKind::BrokenPipe => break, // the peer hung up first
+            Err(FrameError::Io(e)) => return Err(e),
+            Err(e) => eprintln!("Not sending message: {}", e),
+        }
     }
     Ok(())
 }
 
+/// Pass messages both ways until the GUI is done, then close the connection so that the
+/// peer still gets everything that was queued (the last one normally a ChessQUIT).
+fn run_connection(
+    mut stream: TcpStream,
+    rx: Receiver<ProtocolMsg>,
+    tx_to_gui: Sender<Result<ProtocolMsg, ProtocolError>>,
+) -> io::Result<()> {
+    let reader = spawn_reader(stream.try_clone()?, tx_to_gui);
+    let result = send_loop(&mut stream, rx);
+
+    // no more writing, the peer reads what is left and then sees the end of the stream
+    let _ = stream.shutdown(Shutdown::Write);
+    wait_for(&reader, CLOSE_WAIT);
+    let _ = stream.shutdown(Shutdown::Both); // wakes the reader if the peer never closed
+    let _ = reader.join();
+    result
+}
+
+/// Whether the thread finished within `timeout`.
+fn wait_for<T>(handle: &thread::JoinHandle<T>, timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    while !handle.is_finished() {
+        if Instant::now() >= deadline {
+            return false;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    true
+}
+
+pub fn start_client_with_channel(
+    addr: &str,
+    rx: Receiver<ProtocolMsg>,
+    tx_to_gui: Sender<Result<ProtocolMsg, ProtocolError>>,
+) -> io::Result<()> {
+    let stream = TcpStream::connect(addr)?;
+
+    println!("Connected to server at {}", addr);
+
+    run_connection(stream, rx, tx_to_gui)
+}
+
 pub fn start_server_with_channel(
     addr: &str,
-    rx: Receiver<ProtocolMsg>, 
-    tx_to_gui: Sender<ProtocolMsg>,
-) -> std::io::Result<()> {
+    rx: Receiver<ProtocolMsg>,
+    tx_to_gui: Sender<Result<ProtocolMsg, ProtocolError>>,
+) -> io::Result<()> {
     let listener = TcpListener::bind(addr)?;
-    let (mut stream, _) = listener.accept()?;
-    let mut reader = stream.try_clone()?;
+    let (stream, _) = listener.accept()?;
 
     println!("Client connected to server at {}", addr);
 
-    let tx_clone = tx_to_gui.clone();
-    thread::spawn(move || {
-        let mut buf = [0u8; 128];
-        while let Ok(n) = reader.read(&mut buf) {
-            if n == 0 { break; }
-            let raw = String::from_utf8_lossy(&buf[..n]);
-            println!("Raw msg received: {}", raw);
-
-            if let Some(msg) = ProtocolMsg::deserialize(&raw) {
-                tx_clone.send(msg).unwrap(); 
-            }
+    run_connection(stream, rx, tx_to_gui)
+}
+
+/// Both ends a front end holds: messages for the opponent go in the Sender, whatever
+/// the opponent sends comes out of the Receiver.
+pub type Channels = (Sender<ProtocolMsg>, Receiver<Result<ProtocolMsg, ProtocolError>>);
+
+/// Connect to the opponent `mode` asks for (or start the engine) on its own thread.
+/// None when there is no opponent. Messages sent before the connection is up are queued.
+/// The thread ends once the Sender is dropped and everything queued has gone out.
+pub fn spawn_opponent(mode: &Mode, start_fen: Option<&str>) -> Option<(Channels, thread::JoinHandle<()>)> {
+    let (tx_to_network, rx) = mpsc::channel::<ProtocolMsg>();
+    let (tx_to_gui, rx_from_network) = mpsc::channel::<Result<ProtocolMsg, ProtocolError>>();
+    let thread = match mode.clone() {
+        Mode::Client { connect } => {
+            thread::spawn(move || {
+                if let Err(e) = start_client_with_channel(&connect, rx, tx_to_gui) {
+                    eprintln!("Could not connect to {}: {}", connect, e);
+                }
+            })
         }
-    });
-    while let Ok(msg) = rx.recv() {
-                let serialized = msg.serialize();
-        stream.write_all(serialized.as_bytes())?;
+        Mode::Server { bind } => {
+            thread::spawn(move || {
+                if let Err(e) = start_server_with_channel(&bind, rx, tx_to_gui) {
+                    eprintln!("Server on {} failed: {}", bind, e);
+                }
+            })
+        }
+        Mode::Uci { engine, movetime } => {
+            let start_fen = start_fen.map(str::to_string);
+            thread::spawn(move || {
+                if let Err(e) = uci::start_engine_with_channel(&engine, start_fen.as_deref(), movetime, rx, tx_to_gui) {
+                    eprintln!("Engine {} failed: {}", engine, e);
+                }
+            })
+        }
+        Mode::Local | Mode::Replay { .. } => return None,
+    };
+    Some(((tx_to_network, rx_from_network), thread))
+}
+
+/// Wait for the thread from `spawn_opponent` after the front end has let go of its Sender.
+/// Gives up after a few seconds, e.g. when a server is still waiting for its client.
+pub fn hang_up(thread: thread::JoinHandle<()>) {
+    if wait_for(&thread, SHUTDOWN_WAIT) {
+        let _ = thread.join();
+    } else {
+        eprintln!("Network thread did not finish, leaving anyway");
     }
-    Ok(())
+}
+
+/// Set once Ctrl-C is pressed, so a front end can leave the same way as with its quit
+/// command instead of dying with the opponent never told. Call it once, a process only
+/// gets one Ctrl-C handler.
+pub fn ctrl_c_flag() -> Arc<AtomicBool> {
+    let flag = Arc::new(AtomicBool::new(false));
+    let handler_flag = flag.clone();
+    if let Err(e) = ctrlc::set_handler(move || handler_flag.store(true, Ordering::SeqCst)) {
+        eprintln!("Ctrl-C will not tell the opponent: {}", e);
+    }
+    flag
 }
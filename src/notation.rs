@@ -0,0 +1,201 @@
+// Standard Algebraic Notation, https://en.wikipedia.org/wiki/Algebraic_notation_(chess)
+use hermanha_chess::{Board, PieceType, Position};
+
+use crate::rules::{all_squares, has_legal_move, is_in_check, is_legal_move, is_promotion, legal_moves};
+
+/// A move as it was played, for the move list and PGN.
+#[derive(Clone, Debug)]
+pub struct MoveRecord {
+    pub from: Position,
+    pub to: Position,
+    pub promo: Option<PieceType>,
+    pub san: String,
+}
+
+pub fn piece_letter(piece_type: PieceType) -> &'static str {
+    match piece_type {
+        PieceType::Pawn => "",
+        PieceType::Knight => "N",
+        PieceType::Bishop => "B",
+        PieceType::Rook => "R",
+        PieceType::Queen => "Q",
+        PieceType::King => "K",
+    }
+}
+
+fn file_char(col: i8) -> char {
+    (b'a' + col as u8) as char
+}
+
+fn rank_char(row: i8) -> char {
+    (b'1' + row as u8) as char
+}
+
+pub fn square_name(pos: Position) -> String {
+    format!("{}{}", file_char(pos.col), rank_char(pos.row))
+}
+
+/// SAN for `from -> to` on `board`, which is the position before the move. Returns
+/// None if the move is not legal there.
+pub fn move_to_san(board: &Board, from: Position, to: Position, promo: Option<PieceType>) -> Option<String> {
+    let piece = board.get(from)?;
+    let mut after = board.clone();
+    if after.move_piece(from, to, promo).is_err() {
+        return None;
+    }
+
+    let mut san = String::new();
+    if piece.piece_type == PieceType::King && (to.col - from.col).abs() == 2 {
+        san.push_str(if to.col > from.col { "O-O" } else { "O-O-O" });
+    } else {
+        let is_capture = board.get(to).is_some() || (piece.piece_type == PieceType::Pawn && from.col != to.col);
+
+        if piece.piece_type == PieceType::Pawn {
+            if is_capture {
+                san.push(file_char(from.col));
+            }
+        } else {
+            san.push_str(piece_letter(piece.piece_type));
+            san.push_str(&disambiguation(board, from, to));
+        }
+        if is_capture {
+            san.push('x');
+        }
+        san.push_str(&square_name(to));
+        if let Some(promo) = promo {
+            san.push('=');
+            san.push_str(piece_letter(promo));
+        }
+    }
+
+    if is_in_check(&after, after.move_turn) {
+        san.push(if has_legal_move(&after) { '+' } else { '#' });
+    }
+    Some(san)
+}
+
+/// File, rank or both of `from` when another piece of the same kind could also go to `to`.
+fn disambiguation(board: &Board, from: Position, to: Position) -> String {
+    let Some(piece) = board.get(from) else { return String::new() };
+    let rivals: Vec<Position> = all_squares()
+        .filter(|&pos| pos != from)
+        .filter(|&pos| {
+            matches!(board.get(pos), Some(p) if p.piece_type == piece.piece_type && p.color == piece.color)
+        })
+        .filter(|&pos| is_legal_move(board, pos, to))
+        .collect();
+
+    if rivals.is_empty() {
+        String::new()
+    } else if rivals.iter().all(|r| r.col != from.col) {
+        file_char(from.col).to_string()
+    } else if rivals.iter().all(|r| r.row != from.row) {
+        rank_char(from.row).to_string()
+    } else {
+        square_name(from)
+    }
+}
+
+fn letter_to_piece(letter: char) -> Option<PieceType> {
+    match letter {
+        'N' => Some(PieceType::Knight),
+        'B' => Some(PieceType::Bishop),
+        'R' => Some(PieceType::Rook),
+        'Q' => Some(PieceType::Queen),
+        'K' => Some(PieceType::King),
+        _ => None,
+    }
+}
+
+/// The move `san` stands for on `board`. Also takes "0-0" castling, promotions without
+/// the '=', and missing or needless capture marks, check marks and disambiguation.
+pub fn san_to_move(board: &Board, san: &str) -> Result<(Position, Position, Option<PieceType>), String> {
+    let text = san.trim_end_matches(['+', '#', '!', '?']);
+    let moves = legal_moves(board);
+
+    let castle_col = match text {
+        "O-O" | "0-0" => Some(6),
+        "O-O-O" | "0-0-0" => Some(2),
+        _ => None,
+    };
+    if let Some(col) = castle_col {
+        return moves
+            .into_iter()
+            .find(|&(from, to)| {
+                matches!(board.get(from), Some(p) if p.piece_type == PieceType::King)
+                    && from.col == 4
+                    && to.col == col
+            })
+            .map(|(from, to)| (from, to, None))
+            .ok_or_else(|| format!("{} is not legal here", san));
+    }
+
+    let chars: Vec<char> = text.chars().filter(|&c| c != 'x' && c != '-' && c != '=').collect();
+    let bad = || format!("can not read move {}", san);
+
+    // a trailing piece letter right after the rank is the promotion
+    let (chars, promo) = match chars.as_slice() {
+        [rest @ .., rank, letter] if rank.is_ascii_digit() && letter.is_ascii_uppercase() => {
+            let promo = letter_to_piece(*letter).filter(|&p| p != PieceType::King).ok_or_else(bad)?;
+            (&chars[..rest.len() + 1], Some(promo))
+        }
+        _ => (chars.as_slice(), None),
+    };
+    let (piece_type, chars) = match chars.first().and_then(|&c| letter_to_piece(c)) {
+        Some(piece_type) => (piece_type, &chars[1..]),
+        None => (PieceType::Pawn, chars),
+    };
+    let [hint @ .., file, rank] = chars else { return Err(bad()) };
+    if hint.len() > 2 || !('a'..='h').contains(file) || !('1'..='8').contains(rank) {
+        return Err(bad());
+    }
+    let to = Position::new(*rank as i8 - '1' as i8, *file as i8 - 'a' as i8);
+    let mut from_file = None;
+    let mut from_rank = None;
+    for &c in hint {
+        match c {
+            'a'..='h' => from_file = Some(c as i8 - 'a' as i8),
+            '1'..='8' => from_rank = Some(c as i8 - '1' as i8),
+            _ => return Err(bad()),
+        }
+    }
+
+    let matching: Vec<(Position, Position)> = moves
+        .into_iter()
+        .filter(|&(from, target)| {
+            target == to
+                && matches!(board.get(from), Some(p) if p.piece_type == piece_type)
+                && from_file.is_none_or(|f| from.col == f)
+                && from_rank.is_none_or(|r| from.row == r)
+        })
+        .collect();
+    match matching.as_slice() {
+        [] => Err(format!("{} is not legal here", san)),
+        [(from, to)] => {
+            match (is_promotion(board, *from, *to), promo) {
+                (true, None) => Err(format!("{} needs a promotion piece", san)),
+                (false, Some(_)) => Err(format!("{} is not a promotion", san)),
+                _ => Ok((*from, *to, promo)),
+            }
+        }
+        _ => Err(format!("{} is ambiguous", san)),
+    }
+}
+
+/// Moves in numbered pairs, "1. e4 e5", "2. Nf3 Nc6", ... A game starting with Black
+/// to move opens with "1... e5". `first_number` is the fullmove number of the first move.
+pub fn numbered_pairs(sans: &[String], first_number: u32, black_first: bool) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut rest = sans;
+    let mut number = first_number;
+    if black_first && !rest.is_empty() {
+        lines.push(format!("{}... {}", number, rest[0]));
+        rest = &rest[1..];
+        number += 1;
+    }
+    for pair in rest.chunks(2) {
+        lines.push(format!("{}. {}", number, pair.join(" ")));
+        number += 1;
+    }
+    lines
+}
@@ -0,0 +1,276 @@
+// Portable Game Notation import and export, https://www.saremba.de/chessgml/standards/pgn/pgn-complete.htm
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hermanha_chess::Board;
+
+use crate::helper::{fen_to_board, make_move, PositionInfo};
+use crate::notation::{move_to_san, numbered_pairs, san_to_move, MoveRecord};
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+/// Movetext lines are wrapped at this width, as the export format asks.
+const LINE_WIDTH: usize = 79;
+
+/// A game as written to or read from a PGN file.
+#[derive(Clone, Debug)]
+pub struct PgnGame {
+    pub white: String,
+    pub black: String,
+    pub date: String, // YYYY.MM.DD
+    pub result: String, // chesstp game state, "0-0" while the game is on
+    pub mode: Option<String>, // "server"/"client" for network games
+    pub termination: Option<String>,
+    pub start_fen: String,
+    pub moves: Vec<String>, // SAN
+}
+
+/// PGN result for a chesstp game state, "*" for a game that is still going.
+pub fn result_tag(game_state: &str) -> &str {
+    match game_state {
+        "1-0" | "0-1" | "1/2-1/2" => game_state,
+        _ => "*",
+    }
+}
+
+impl PgnGame {
+    pub fn to_pgn(&self) -> String {
+        let result = result_tag(&self.result);
+        let mut tags = vec![
+            ("Event", "Casual game"),
+            ("Site", "?"),
+            ("Date", self.date.as_str()),
+            ("Round", "-"),
+            ("White", self.white.as_str()),
+            ("Black", self.black.as_str()),
+            ("Result", result),
+        ];
+        if let Some(mode) = &self.mode {
+            tags.push(("Mode", mode));
+        }
+        if let Some(termination) = &self.termination {
+            tags.push(("Termination", termination));
+        }
+        if self.start_fen != START_FEN {
+            tags.push(("SetUp", "1"));
+            tags.push(("FEN", &self.start_fen));
+        }
+
+        let mut out = String::new();
+        for (name, value) in tags {
+            out.push_str(&format!("[{} \"{}\"]\n", name, escape(value)));
+        }
+        out.push('\n');
+
+        // the side to move and move number come from the FEN
+        let fields: Vec<&str> = self.start_fen.split_whitespace().collect();
+        let black_first = fields.get(1) == Some(&"b");
+        let first_number = fields.get(5).and_then(|n| n.parse().ok()).unwrap_or(1);
+
+        let mut line = String::new();
+        let words = numbered_pairs(&self.moves, first_number, black_first)
+            .join(" ")
+            .split(' ')
+            .map(str::to_string)
+            .chain([result.to_string()])
+            .collect::<Vec<_>>();
+        for word in words {
+            if !line.is_empty() && line.len() + 1 + word.len() > LINE_WIDTH {
+                out.push_str(&line);
+                out.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        out.push_str(&line);
+        out.push('\n');
+        out
+    }
+
+    /// Write the game over the file it was saved to before.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_pgn())
+    }
+
+    /// Write the game to a new file in `dir`, named after the time and `mode`. A name that
+    /// is taken gets a number added, an existing file is never overwritten.
+    pub fn save_new(&self, dir: &Path, mode: Option<&str>) -> io::Result<PathBuf> {
+        let mut copy = 1;
+        loop {
+            let path = dir.join(file_name(mode, copy));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(mut file) => {
+                    file.write_all(self.to_pgn().as_bytes())?;
+                    return Ok(path);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => copy += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/// Reads the first game in `text`. Comments, NAGs and variations are skipped.
+pub fn parse_pgn(text: &str) -> Result<PgnGame, String> {
+    let mut game = PgnGame {
+        white: "?".to_string(),
+        black: "?".to_string(),
+        date: "????.??.??".to_string(),
+        result: "0-0".to_string(),
+        mode: None,
+        termination: None,
+        start_fen: START_FEN.to_string(),
+        moves: Vec::new(),
+    };
+
+    let mut movetext = String::new();
+    for line in text.lines() {
+        let line = line.trim();
+        if line.starts_with('%') {
+            continue; // escaped line, meant to be ignored
+        }
+        if line.starts_with('[') {
+            if !movetext.trim().is_empty() {
+                break; // tags of the next game
+            }
+            let (name, value) = parse_tag(line)?;
+            match name.as_str() {
+                "White" => game.white = value,
+                "Black" => game.black = value,
+                "Date" => game.date = value,
+                "Result" if value != "*" => game.result = value,
+                "Mode" => game.mode = Some(value),
+                "Termination" => game.termination = Some(value),
+                "FEN" => game.start_fen = value,
+                _ => {}
+            }
+        } else {
+            movetext.push_str(line);
+            movetext.push('\n');
+        }
+    }
+
+    let mut chars = movetext.chars().peekable();
+    let mut depth = 0; // how deep inside variations we are
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => {
+                if !chars.by_ref().any(|c| c == '}') {
+                    return Err("comment is never closed".to_string());
+                }
+            }
+            ';' => while chars.next_if(|&c| c != '\n').is_some() {},
+            '(' => depth += 1,
+            ')' if depth == 0 => return Err("unexpected ) outside a variation".to_string()),
+            ')' => depth -= 1,
+            c if c.is_whitespace() => {}
+            c => {
+                let mut token = c.to_string();
+                while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && !"{}();".contains(c)) {
+                    token.push(c);
+                }
+                if depth > 0 || token.starts_with('$') {
+                    continue;
+                }
+                if ["1-0", "0-1", "1/2-1/2", "*"].contains(&token.as_str()) {
+                    if token != "*" {
+                        game.result = token;
+                    }
+                    break;
+                }
+                // "12." or "12..." in front of the move, sometimes without a space
+                let digits = token.len() - token.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                let san = match token[digits..].strip_prefix('.') {
+                    Some(rest) if digits > 0 => rest.trim_start_matches('.'),
+                    _ => token.as_str(),
+                };
+                let san = san.trim_end_matches(['!', '?']);
+                if !san.is_empty() {
+                    game.moves.push(san.to_string());
+                }
+            }
+        }
+    }
+    if depth > 0 {
+        return Err("variation is never closed".to_string());
+    }
+    Ok(game)
+}
+
+/// `[Name "value"]` to (name, value).
+fn parse_tag(line: &str) -> Result<(String, String), String> {
+    let bad = || format!("bad tag {}", line);
+    let inner = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')).ok_or_else(bad)?;
+    let (name, value) = inner.split_once(char::is_whitespace).ok_or_else(bad)?;
+    let value = value.trim().strip_prefix('"').and_then(|v| v.strip_suffix('"')).ok_or_else(bad)?;
+    Ok((name.to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+/// A game played through on the board.
+pub struct ReplayedGame {
+    pub positions: Vec<(Board, PositionInfo)>, // start position first, one more than moves
+    pub moves: Vec<MoveRecord>,
+}
+
+/// Plays the game through, failing on the first move that is not legal.
+pub fn replay_game(game: &PgnGame) -> Result<ReplayedGame, String> {
+    let (mut board, mut info) = fen_to_board(&game.start_fen)?;
+    let mut positions = vec![(board.clone(), info.clone())];
+    let mut records = Vec::new();
+    for (i, san) in game.moves.iter().enumerate() {
+        let failed = |e: String| format!("move {} ({}): {}", i + 1, san, e);
+        let (from, to, promo) = san_to_move(&board, san).map_err(failed)?;
+        let before = board.clone();
+        make_move(&mut board, &mut info, from, to, promo).map_err(failed)?;
+        let san = move_to_san(&before, from, to, promo).unwrap_or_else(|| san.clone());
+        records.push(MoveRecord { from, to, promo, san });
+        positions.push((board.clone(), info.clone()));
+    }
+    Ok(ReplayedGame { positions, moves: records })
+}
+
+/// (year, month, day, hour, minute, second) in UTC.
+fn utc_now() -> (i64, u32, u32, u32, u32, u32) {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let (days, rest) = (secs.div_euclid(86400), secs.rem_euclid(86400) as u32);
+
+    // days since 1970-01-01 to a civil date, http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day, rest / 3600, rest / 60 % 60, rest % 60)
+}
+
+/// Today's date the way the Date tag wants it.
+pub fn today() -> String {
+    let (year, month, day, ..) = utc_now();
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+/// A file name for a game saved now, e.g. "game-20240501-183000-server.pgn", or
+/// "game-20240501-183000-server-2.pgn" for the second `copy` in the same second.
+fn file_name(mode: Option<&str>, copy: u32) -> String {
+    let (year, month, day, hour, minute, second) = utc_now();
+    let suffix = mode.map(|m| format!("-{}", m)).unwrap_or_default();
+    let copy = if copy > 1 { format!("-{}", copy) } else { String::new() };
+    format!("game-{:04}{:02}{:02}-{:02}{:02}{:02}{}{}.pgn", year, month, day, hour, minute, second, suffix, copy)
+}
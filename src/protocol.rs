@@ -1,53 +1,424 @@
 // https://github.com/INDA25PlusPlus/chesstp-spec
 // https://www.chessprogramming.org/Forsyth-Edwards_Notation
+use std::fmt;
+
+use crate::clock::TimeControl;
+use crate::helper::fen_to_board;
+use crate::network::FRAME_LEN;
+
 pub enum ProtocolMsg {
     Move(MoveMsg),
     Quit(QuitMsg),
+    Hello(HelloMsg), // our extension, see HelloMsg
+    Takeback(TakebackMsg), // our extension, see TakebackMsg
+    Game(GameActionMsg), // our extension, see GameActionMsg
+    Clock(ClockMsg), // our extension, see ClockMsg
 }
 impl ProtocolMsg {
     pub fn serialize(&self) -> String {
         match self {
             ProtocolMsg::Move(m) => m.serialize(),
             ProtocolMsg::Quit(q) => q.serialize(),
+            ProtocolMsg::Hello(h) => h.serialize(),
+            ProtocolMsg::Takeback(t) => t.serialize(),
+            ProtocolMsg::Game(g) => g.serialize(),
+            ProtocolMsg::Clock(c) => c.serialize(),
+        }
+    }
+    pub fn deserialize(raw: &str) -> Result<Self, ProtocolError> {
+        check_frame(raw)?;
+        match header(raw) {
+            "ChessMOVE" => MoveMsg::deserialize(raw).map(ProtocolMsg::Move),
+            "ChessQUIT" => QuitMsg::deserialize(raw).map(ProtocolMsg::Quit),
+            "ChessHELO" => HelloMsg::deserialize(raw).map(ProtocolMsg::Hello),
+            "ChessTAKE" => TakebackMsg::deserialize(raw).map(ProtocolMsg::Takeback),
+            "ChessGAME" => GameActionMsg::deserialize(raw).map(ProtocolMsg::Game),
+            "ChessTIME" => ClockMsg::deserialize(raw).map(ProtocolMsg::Clock),
+            other => Err(ProtocolError::UnknownHeader(other.to_string())),
+        }
+    }
+}
+
+/// Why an incoming frame could not be turned into a ProtocolMsg.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProtocolError {
+    UnknownHeader(String),
+    WrongLength(usize),
+    NonAscii,
+    MissingField(&'static str),
+    BadMove(String),
+    BadGameState(String),
+    BadFen(String),
+    BadPadding(String),
+    BadColor(String),
+    BadTakeback(String),
+    BadGameAction(String),
+    BadTime(String),
+}
+
+impl ProtocolError {
+    /// Short reason to put in the ChessQUIT we answer a broken message with.
+    pub fn quit_reason(&self) -> &'static str {
+        match self {
+            ProtocolError::UnknownHeader(_) => "desync: unknown header",
+            ProtocolError::WrongLength(_) => "desync: wrong message length",
+            ProtocolError::NonAscii => "desync: non-ascii message",
+            ProtocolError::MissingField(_) => "desync: missing field",
+            ProtocolError::BadMove(_) => "desync: bad move",
+            ProtocolError::BadGameState(_) => "desync: bad game state",
+            ProtocolError::BadFen(_) => "desync: bad fen",
+            ProtocolError::BadPadding(_) => "desync: bad padding",
+            ProtocolError::BadColor(_) => "desync: bad color",
+            ProtocolError::BadTakeback(_) => "desync: bad takeback",
+            ProtocolError::BadGameAction(_) => "desync: bad game action",
+            ProtocolError::BadTime(_) => "desync: bad time",
         }
     }
-    pub fn deserialize(raw: &str) -> Option<Self> {
-        if raw.starts_with("ChessMOVE") {
-            MoveMsg::deserialize(raw).map(ProtocolMsg::Move)
-        } else if raw.starts_with("ChessQUIT") {
-            QuitMsg::deserialize(raw).map(ProtocolMsg::Quit)
-        } else {
-            None
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::UnknownHeader(h) => write!(f, "unknown header {:?}", h),
+            ProtocolError::WrongLength(n) => write!(f, "message is {} bytes, expected {}", n, FRAME_LEN),
+            ProtocolError::NonAscii => write!(f, "message contains non-ascii bytes"),
+            ProtocolError::MissingField(name) => write!(f, "missing field: {}", name),
+            ProtocolError::BadMove(m) => write!(f, "bad move string {:?}", m),
+            ProtocolError::BadGameState(s) => write!(f, "bad game state {:?}", s),
+            ProtocolError::BadFen(fen) => write!(f, "bad fen {:?}", fen),
+            ProtocolError::BadPadding(p) => write!(f, "bad padding {:?}", p),
+            ProtocolError::BadColor(c) => write!(f, "bad color {:?}", c),
+            ProtocolError::BadTakeback(t) => write!(f, "bad takeback {:?}", t),
+            ProtocolError::BadGameAction(a) => write!(f, "bad game action {:?}", a),
+            ProtocolError::BadTime(t) => write!(f, "bad time {:?}", t),
         }
     }
 }
 
+impl std::error::Error for ProtocolError {}
+
+fn check_frame(raw: &str) -> Result<(), ProtocolError> {
+    if !raw.is_ascii() {
+        return Err(ProtocolError::NonAscii);
+    }
+    if raw.len() != FRAME_LEN {
+        return Err(ProtocolError::WrongLength(raw.len()));
+    }
+    Ok(())
+}
+
+fn header(raw: &str) -> &str {
+    raw.split(':').next().unwrap_or("")
+}
+
+/// Fill a message up to FRAME_LEN with '0'.
+fn pad_frame(mut msg: String) -> String {
+    let padding_len = FRAME_LEN.saturating_sub(msg.len());
+    msg.push_str(&"0".repeat(padding_len));
+    msg
+}
+
+/// Everything after the last field has to be '0' padding.
+fn check_padding(padding: &str) -> Result<(), ProtocolError> {
+    if padding.bytes().all(|b| b == b'0') {
+        Ok(())
+    } else {
+        Err(ProtocolError::BadPadding(padding.to_string()))
+    }
+}
+
 pub struct QuitMsg {
     pub reason: String, // optional message ("desync", "panic", "user quit", etc.)
 }
 
 impl QuitMsg {
     pub fn serialize(&self) -> String {
-        let mut msg = format!("ChessQUIT:{}:", self.reason);
-        let padding_len = 128 - msg.len();
-        msg.push_str(&"0".repeat(padding_len));
-        msg
+        pad_frame(format!("ChessQUIT:{}:", self.reason))
     }
 
-    pub fn deserialize(raw: &str) -> Option<Self> {
-        if !raw.starts_with("ChessQUIT") {
-            return None;
-        }
-        let parts: Vec<&str> = raw.split(':').collect();
-        if parts.len() < 2 {
-            return None;
+    pub fn deserialize(raw: &str) -> Result<Self, ProtocolError> {
+        check_frame(raw)?;
+        if header(raw) != "ChessQUIT" {
+            return Err(ProtocolError::UnknownHeader(header(raw).to_string()));
         }
-        Some(QuitMsg {
-            reason: parts[1].to_string(),
+        // the reason may contain ':' itself, only the padding after the last one can not
+        let body = &raw["ChessQUIT:".len().min(raw.len())..];
+        let Some((reason, padding)) = body.rsplit_once(':') else {
+            return Err(ProtocolError::MissingField("reason"));
+        };
+        check_padding(padding)?;
+        Ok(QuitMsg {
+            reason: reason.to_string(),
         })
     }
 }
 
+/// Colour a player asks for with --color.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    White,
+    Black,
+    Random,
+}
+
+impl ColorChoice {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColorChoice::White => "white",
+            ColorChoice::Black => "black",
+            ColorChoice::Random => "random",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "white" => Some(ColorChoice::White),
+            "black" => Some(ColorChoice::Black),
+            "random" => Some(ColorChoice::Random),
+            _ => None,
+        }
+    }
+
+    /// Pick an actual colour, tossing a coin for Random.
+    pub fn resolve(&self) -> hermanha_chess::Color {
+        match self {
+            ColorChoice::White => hermanha_chess::Color::White,
+            ColorChoice::Black => hermanha_chess::Color::Black,
+            ColorChoice::Random if coin_flip() => hermanha_chess::Color::White,
+            ColorChoice::Random => hermanha_chess::Color::Black,
+        }
+    }
+}
+
+impl From<hermanha_chess::Color> for ColorChoice {
+    fn from(color: hermanha_chess::Color) -> Self {
+        match color {
+            hermanha_chess::Color::White => ColorChoice::White,
+            hermanha_chess::Color::Black => ColorChoice::Black,
+        }
+    }
+}
+
+/// RandomState is seeded randomly per process, good enough for picking colours.
+fn coin_flip() -> bool {
+    use std::hash::{BuildHasher, Hasher};
+    std::collections::hash_map::RandomState::new().build_hasher().finish() & 1 == 1
+}
+
+/// Colour the server plays. The server's own --color wins, a server without one (or
+/// with random) goes along with what the client asked for.
+pub fn server_color(server: Option<ColorChoice>, client: ColorChoice) -> hermanha_chess::Color {
+    use hermanha_chess::Color;
+    match (server, client) {
+        (Some(ColorChoice::White), _) => Color::White,
+        (Some(ColorChoice::Black), _) => Color::Black,
+        (_, ColorChoice::White) => Color::Black,
+        (_, ColorChoice::Black) => Color::White,
+        (_, ColorChoice::Random) => ColorChoice::Random.resolve(),
+    }
+}
+
+/// Colour handshake, not part of chesstp. Right after connecting the client sends
+/// the colour it wants ("white", "black" or "random"), the server decides and answers
+/// with the colour the client plays ("white" or "black"). No moves before that.
+/// The time control ("5+3", or "none" for no clocks) is agreed the same way.
+/*
+"ChessHELO:white:5+3:000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000"
+*/
+pub struct HelloMsg {
+    pub color: ColorChoice,
+    pub time: Option<TimeControl>,
+}
+
+impl HelloMsg {
+    pub fn serialize(&self) -> String {
+        let time = self.time.map_or("none".to_string(), |t| t.to_string());
+        pad_frame(format!("ChessHELO:{}:{}:", self.color.as_str(), time))
+    }
+
+    pub fn deserialize(raw: &str) -> Result<Self, ProtocolError> {
+        check_frame(raw)?;
+        if header(raw) != "ChessHELO" {
+            return Err(ProtocolError::UnknownHeader(header(raw).to_string()));
+        }
+        let parts: Vec<&str> = raw.splitn(4, ':').collect();
+        let field = |i: usize, name: &'static str| parts.get(i).copied().ok_or(ProtocolError::MissingField(name));
+        let color = field(1, "color")?;
+        let time = field(2, "time")?;
+        let padding = field(3, "padding")?;
+
+        let color = ColorChoice::parse(color).ok_or_else(|| ProtocolError::BadColor(color.to_string()))?;
+        let time = match time {
+            "none" => None,
+            t => Some(TimeControl::parse(t).map_err(|_| ProtocolError::BadTime(t.to_string()))?),
+        };
+        check_padding(padding)?;
+        Ok(HelloMsg { color, time })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TakebackAction {
+    Request,
+    Accept,
+    Decline,
+}
+
+impl TakebackAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TakebackAction::Request => "request",
+            TakebackAction::Accept => "accept",
+            TakebackAction::Decline => "decline",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "request" => Some(TakebackAction::Request),
+            "accept" => Some(TakebackAction::Accept),
+            "decline" => Some(TakebackAction::Decline),
+            _ => None,
+        }
+    }
+}
+
+/// Takeback, not part of chesstp. A player asks to take back their last move: `plies`
+/// is 1 or 2 (2 when the opponent already answered it) and `moves` is how many moves
+/// were played when asking. The opponent answers accept or decline with the same
+/// numbers. A request that crossed a move is declined, the boards only roll back on
+/// accept, so both sides stay in step.
+/*
+"ChessTAKE:request:1:14:00000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000"
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TakebackMsg {
+    pub action: TakebackAction,
+    pub plies: usize,
+    pub moves: usize,
+}
+
+impl TakebackMsg {
+    pub fn serialize(&self) -> String {
+        pad_frame(format!("ChessTAKE:{}:{}:{}:", self.action.as_str(), self.plies, self.moves))
+    }
+
+    pub fn deserialize(raw: &str) -> Result<Self, ProtocolError> {
+        check_frame(raw)?;
+        if header(raw) != "ChessTAKE" {
+            return Err(ProtocolError::UnknownHeader(header(raw).to_string()));
+        }
+        let parts: Vec<&str> = raw.splitn(5, ':').collect();
+        let field = |i: usize, name: &'static str| parts.get(i).copied().ok_or(ProtocolError::MissingField(name));
+        let action = field(1, "action")?;
+        let plies = field(2, "plies")?;
+        let moves = field(3, "moves")?;
+        let padding = field(4, "padding")?;
+
+        let bad = || ProtocolError::BadTakeback(parts[1..4].join(":"));
+        let action = TakebackAction::parse(action).ok_or_else(bad)?;
+        let plies = plies.parse().ok().filter(|p| (1..=2).contains(p)).ok_or_else(bad)?;
+        let moves = moves.parse().ok().filter(|&m| m >= plies).ok_or_else(bad)?;
+        check_padding(padding)?;
+        Ok(TakebackMsg { action, plies, moves })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GameAction {
+    Resign,
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+    Abort,
+}
+
+impl GameAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            GameAction::Resign => "resign",
+            GameAction::OfferDraw => "offer-draw",
+            GameAction::AcceptDraw => "accept-draw",
+            GameAction::DeclineDraw => "decline-draw",
+            GameAction::Abort => "abort",
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "resign" => Some(GameAction::Resign),
+            "offer-draw" => Some(GameAction::OfferDraw),
+            "accept-draw" => Some(GameAction::AcceptDraw),
+            "decline-draw" => Some(GameAction::DeclineDraw),
+            "abort" => Some(GameAction::Abort),
+            _ => None,
+        }
+    }
+}
+
+/// Ending a game without a move, not part of chesstp. The sender resigns, offers a
+/// draw, answers the opponent's offer, or aborts a game before both sides moved.
+/*
+"ChessGAME:offer-draw:000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000"
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GameActionMsg {
+    pub action: GameAction,
+}
+
+impl GameActionMsg {
+    pub fn serialize(&self) -> String {
+        pad_frame(format!("ChessGAME:{}:", self.action.as_str()))
+    }
+
+    pub fn deserialize(raw: &str) -> Result<Self, ProtocolError> {
+        check_frame(raw)?;
+        if header(raw) != "ChessGAME" {
+            return Err(ProtocolError::UnknownHeader(header(raw).to_string()));
+        }
+        let parts: Vec<&str> = raw.splitn(3, ':').collect();
+        if parts.len() < 3 {
+            return Err(ProtocolError::MissingField("action"));
+        }
+        let action = GameAction::parse(parts[1]).ok_or_else(|| ProtocolError::BadGameAction(parts[1].to_string()))?;
+        check_padding(parts[2])?;
+        Ok(GameActionMsg { action })
+    }
+}
+
+/// Clock times, not part of chesstp. Sent right after each ChessMOVE by the player who
+/// moved, with both players' remaining time in milliseconds as their clock shows it.
+/// A player whose time runs out says so with a 0 for themselves; nobody else decides
+/// that, so both sides agree on who flagged.
+/*
+"ChessTIME:297000:300000:000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000000"
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockMsg {
+    pub white_ms: u64,
+    pub black_ms: u64,
+}
+
+impl ClockMsg {
+    pub fn serialize(&self) -> String {
+        pad_frame(format!("ChessTIME:{}:{}:", self.white_ms, self.black_ms))
+    }
+
+    pub fn deserialize(raw: &str) -> Result<Self, ProtocolError> {
+        check_frame(raw)?;
+        if header(raw) != "ChessTIME" {
+            return Err(ProtocolError::UnknownHeader(header(raw).to_string()));
+        }
+        let parts: Vec<&str> = raw.splitn(4, ':').collect();
+        let field = |i: usize, name: &'static str| parts.get(i).copied().ok_or(ProtocolError::MissingField(name));
+        let millis = |s: &str| s.parse::<u64>().map_err(|_| ProtocolError::BadTime(s.to_string()));
+        let white_ms = millis(field(1, "white time")?)?;
+        let black_ms = millis(field(2, "black time")?)?;
+        check_padding(field(3, "padding")?)?;
+        Ok(ClockMsg { white_ms, black_ms })
+    }
+}
+
 pub struct MoveMsg {
     pub move_str: String,   // "E2E40"
     pub game_state: String, // "0-0"
@@ -59,35 +430,79 @@ pub struct MoveMsg {
 */
 impl MoveMsg {
     pub fn serialize(&self) -> String {
-        let mut msg = format!(
+        let msg = format!(
             "ChessMOVE:{}:{}:{}:",
             self.move_str, self.game_state, self.fen
         );
         //println!("Serialized msg: {}", msg);
-        let padding_len = 128 - msg.len();
-        msg.push_str(&"0".repeat(padding_len));
-        msg
+        pad_frame(msg)
     }
-    pub fn deserialize(raw: &str) -> Option<Self> {
+    pub fn deserialize(raw: &str) -> Result<Self, ProtocolError> {
         //println!("Raw msg to deserialize: {}", raw);
+        check_frame(raw)?;
+        if header(raw) != "ChessMOVE" {
+            return Err(ProtocolError::UnknownHeader(header(raw).to_string()));
+        }
         let parts: Vec<&str> = raw.split(':').collect();
-        if parts.len() < 4 || !raw.starts_with("ChessMOVE") {
-            return None;
+        let field = |i: usize, name: &'static str| parts.get(i).copied().ok_or(ProtocolError::MissingField(name));
+        let move_str = field(1, "move")?;
+        let game_state = field(2, "game state")?;
+        let fen = field(3, "fen")?;
+        let padding = field(4, "padding")?;
+        if parts.len() > 5 {
+            return Err(ProtocolError::BadPadding(parts[4..].join(":")));
+        }
+
+        parse_move_str(move_str)?;
+        if !GAME_STATES.contains(&game_state) {
+            return Err(ProtocolError::BadGameState(game_state.to_string()));
+        }
+        if fen_to_board(fen).is_err() {
+            return Err(ProtocolError::BadFen(fen.to_string()));
         }
-        Some(MoveMsg {
-            move_str: parts[1].to_string(),
-            game_state: parts[2].to_string(),
-            fen: parts[3].to_string(),
+        check_padding(padding)?;
+
+        Ok(MoveMsg {
+            move_str: move_str.to_string(),
+            game_state: game_state.to_string(),
+            fen: fen.to_string(),
         })
     }
 }
 
+/// Values allowed in the game state field of a ChessMOVE.
+pub const GAME_STATES: [&str; 4] = ["0-0", "1-0", "0-1", "1/2-1/2"];
+
+/// Split a move string like "E7E8Q" into from, to and promotion piece.
+pub fn parse_move_str(
+    mv: &str,
+) -> Result<(hermanha_chess::Position, hermanha_chess::Position, Option<hermanha_chess::PieceType>), ProtocolError> {
+    use hermanha_chess::PieceType;
+
+    let bad = || ProtocolError::BadMove(mv.to_string());
+    if mv.len() != 5 || !mv.is_ascii() {
+        return Err(bad());
+    }
+    let from = square_to_position(&mv[0..2]).ok_or_else(bad)?;
+    let to = square_to_position(&mv[2..4]).ok_or_else(bad)?;
+    let promo = match &mv[4..5] {
+        "Q" | "q" => Some(PieceType::Queen),
+        "R" | "r" => Some(PieceType::Rook),
+        "B" | "b" => Some(PieceType::Bishop),
+        "N" | "n" | "k" => Some(PieceType::Knight), // "k" is ambiguous, here Knight
+        "0" => None,
+        _ => return Err(bad()),
+    };
+    Ok((from, to, promo))
+}
+
 /// Convert a square string like "E2" to a Position
 pub fn square_to_position(sq: &str) -> Option<hermanha_chess::Position> {
     if sq.len() != 2 { return None; }
     let mut chars = sq.chars();
-    let col = chars.next()? as u8;
+    let col = chars.next()?.to_ascii_uppercase();
     let row = chars.next()?.to_digit(10)? as i8 - 1;
+    if !('A'..='H').contains(&col) || !(0..8).contains(&row) { return None; }
     let col_idx = (col as i8) - ('A' as i8);
     Some(hermanha_chess::Position::new(row, col_idx))
 }
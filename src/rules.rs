@@ -0,0 +1,156 @@
+// Move generation on top of hermanha_chess::Board. The board only tells us whether a
+// single move is legal, so candidates are found by piece geometry and then tried on a
+// cloned board.
+use hermanha_chess::{Board, Color, MoveOk, PieceType, Position};
+
+const KNIGHT_STEPS: [(i8, i8); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+const KING_STEPS: [(i8, i8); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+const ROOK_DIRS: [(i8, i8); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+const BISHOP_DIRS: [(i8, i8); 4] = [(1, 1), (-1, 1), (-1, -1), (1, -1)];
+
+pub fn opponent(color: Color) -> Color {
+    match color {
+        Color::White => Color::Black,
+        Color::Black => Color::White,
+    }
+}
+
+fn on_board(row: i8, col: i8) -> bool {
+    (0..8).contains(&row) && (0..8).contains(&col)
+}
+
+/// All squares, rank 1 first.
+pub fn all_squares() -> impl Iterator<Item = Position> {
+    (0..8).flat_map(|row| (0..8).map(move |col| Position::new(row, col)))
+}
+
+/// Squares a piece could reach by its movement pattern, ignoring checks. Pawn pushes,
+/// double pushes and both diagonals are included, castling as the two-square king step.
+fn candidate_targets(board: &Board, from: Position) -> Vec<Position> {
+    let Some(piece) = board.get(from) else { return Vec::new() };
+    let mut targets = Vec::new();
+    let mut steps = |steps: &[(i8, i8)]| {
+        for (dr, dc) in steps {
+            if on_board(from.row + dr, from.col + dc) {
+                targets.push(Position::new(from.row + dr, from.col + dc));
+            }
+        }
+    };
+    match piece.piece_type {
+        PieceType::Pawn => {
+            let dir = if piece.color == Color::White { 1 } else { -1 };
+            steps(&[(dir, 0), (2 * dir, 0), (dir, 1), (dir, -1)]);
+        }
+        PieceType::Knight => steps(&KNIGHT_STEPS),
+        PieceType::King => {
+            steps(&KING_STEPS);
+            steps(&[(0, 2), (0, -2)]);
+        }
+        PieceType::Bishop => targets.extend(slide(board, from, &BISHOP_DIRS)),
+        PieceType::Rook => targets.extend(slide(board, from, &ROOK_DIRS)),
+        PieceType::Queen => {
+            targets.extend(slide(board, from, &ROOK_DIRS));
+            targets.extend(slide(board, from, &BISHOP_DIRS));
+        }
+    }
+    targets
+}
+
+/// Squares along each direction up to and including the first occupied one.
+fn slide(board: &Board, from: Position, dirs: &[(i8, i8)]) -> Vec<Position> {
+    let mut targets = Vec::new();
+    for (dr, dc) in dirs {
+        let (mut row, mut col) = (from.row + dr, from.col + dc);
+        while on_board(row, col) {
+            let pos = Position::new(row, col);
+            targets.push(pos);
+            if board.get(pos).is_some() {
+                break;
+            }
+            row += dr;
+            col += dc;
+        }
+    }
+    targets
+}
+
+/// Whether `from -> to` is legal for the side to move. Promotions count as legal.
+pub fn is_legal_move(board: &Board, from: Position, to: Position) -> bool {
+    let mut copy = board.clone();
+    copy.move_piece(from, to, None).is_ok()
+}
+
+/// Legal destinations for the piece on `from`, empty if it is not that side's turn.
+pub fn legal_targets(board: &Board, from: Position) -> Vec<Position> {
+    match board.get(from) {
+        Some(piece) if piece.color == board.move_turn => candidate_targets(board, from)
+            .into_iter()
+            .filter(|&to| is_legal_move(board, from, to))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Every legal (from, to) pair for the side to move. A promotion shows up once,
+/// the caller picks the piece.
+pub fn legal_moves(board: &Board) -> Vec<(Position, Position)> {
+    all_squares()
+        .flat_map(|from| legal_targets(board, from).into_iter().map(move |to| (from, to)))
+        .collect()
+}
+
+pub fn has_legal_move(board: &Board) -> bool {
+    all_squares().any(|from| !legal_targets(board, from).is_empty())
+}
+
+/// Whether a piece of `by` attacks `target`, worked out from the squares directly.
+pub fn is_attacked(board: &Board, target: Position, by: Color) -> bool {
+    let is = |row: i8, col: i8, types: &[PieceType]| {
+        on_board(row, col)
+            && matches!(board.get(Position::new(row, col)),
+                Some(p) if p.color == by && types.contains(&p.piece_type))
+    };
+
+    let pawn_row = if by == Color::White { target.row - 1 } else { target.row + 1 };
+    if is(pawn_row, target.col - 1, &[PieceType::Pawn]) || is(pawn_row, target.col + 1, &[PieceType::Pawn]) {
+        return true;
+    }
+    if KNIGHT_STEPS.iter().any(|(dr, dc)| is(target.row + dr, target.col + dc, &[PieceType::Knight])) {
+        return true;
+    }
+    if KING_STEPS.iter().any(|(dr, dc)| is(target.row + dr, target.col + dc, &[PieceType::King])) {
+        return true;
+    }
+
+    let sliders = [
+        (&ROOK_DIRS, [PieceType::Rook, PieceType::Queen]),
+        (&BISHOP_DIRS, [PieceType::Bishop, PieceType::Queen]),
+    ];
+    for (dirs, types) in sliders {
+        for pos in slide(board, target, dirs) {
+            if board.get(pos).is_some() && is(pos.row, pos.col, &types) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+pub fn find_king(board: &Board, color: Color) -> Option<Position> {
+    all_squares().find(|&pos| {
+        matches!(board.get(pos), Some(p) if p.piece_type == PieceType::King && p.color == color)
+    })
+}
+
+pub fn is_in_check(board: &Board, color: Color) -> bool {
+    match find_king(board, color) {
+        Some(king) => is_attacked(board, king, opponent(color)),
+        None => false,
+    }
+}
+
+/// Whether `from -> to` is a pawn move onto the last rank, i.e. needs a promotion piece.
+pub fn is_promotion(board: &Board, from: Position, to: Position) -> bool {
+    let mut copy = board.clone();
+    matches!(copy.move_piece(from, to, None), Ok(MoveOk::NeedsPromotion))
+}
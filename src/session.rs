@@ -0,0 +1,854 @@
+// One game as the player on this side sees it: the board, whose colour is ours, the moves,
+// the clocks, and everything the opponent sends. Front ends (the window, the terminal)
+// only turn clicks or typed moves into calls on GameSession and draw what it holds.
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hermanha_chess::{Board, Color, MoveOk, PieceType, Position};
+
+use crate::cli::Options;
+use crate::clock::{Clock, TimeControl};
+use crate::engine::{self, Difficulty, EngineMove};
+use crate::helper::{
+    apply_message_to_board, board_move_to_message, board_to_fen, fen_to_board, make_move, position_key, verify_fen,
+    PositionInfo,
+};
+use crate::network::Channels;
+use crate::notation::{self, MoveRecord};
+use crate::pgn;
+use crate::protocol::{
+    parse_move_str, server_color, ClockMsg, ColorChoice, GameAction, GameActionMsg, HelloMsg, MoveMsg, ProtocolError,
+    ProtocolMsg, QuitMsg, TakebackAction, TakebackMsg,
+};
+use crate::rules::{self, opponent};
+use crate::status::{self, GameOver, GameStatus};
+
+pub struct GameSession {
+    board: Board,
+    position_info: PositionInfo,
+    start: (Board, PositionInfo), // where "new game" goes back to
+    moves: Vec<MoveRecord>, // every move of the game, in order
+    network_mode: Option<&'static str>, // "server", "client" or "uci", None on one machine
+    my_color: Color, // in network games only a guess until the handshake is done
+    ai: Option<Difficulty>, // the computer plays the colour that is not my_color
+    clock: Option<Clock>, // None when playing without clocks
+    negotiating: bool, // network game waiting for the colour handshake
+    desync: Option<String>, // set when the boards no longer agree, the game is over
+    game_over: Option<GameOver>,
+    takeback_sent: Option<TakebackMsg>, // our request, waiting for an answer
+    takeback_prompt: Option<TakebackMsg>, // the opponent's request, waiting for ours
+    draw_offered: bool, // we offered a draw, waiting for an answer
+    draw_prompt: bool, // the opponent offered a draw
+    replaying: Option<usize>, // moves of a loaded game on the board, None when playing
+    history: Vec<String>, // position_key of every position, for threefold repetition
+    positions: Vec<(Board, PositionInfo)>, // every position of the game, for undo
+    redo: Vec<(MoveRecord, Board, PositionInfo)>, // undone moves, the last one on top
+    pgn_path: Option<PathBuf>, // where this game is saved, picked on the first save
+    pgn_dir: Option<PathBuf>, // what --pgn-dir asked for, games are only saved on demand without it
+    color_choice: Option<ColorChoice>, // what --color asked for
+    time_choice: Option<TimeControl>, // what --time asked for
+    net_writer: Option<Sender<ProtocolMsg>>,
+    net_reader: Option<Receiver<Result<ProtocolMsg, ProtocolError>>>,
+    thinking: Option<(usize, Receiver<Option<EngineMove>>)>, // (moves.len(), search) while the computer thinks
+}
+
+impl GameSession {
+    /// A game as the command line asks for it. Network games wait for `connect`.
+    pub fn new(options: &Options) -> Result<Self, String> {
+        let (board, position_info) = match &options.start_fen {
+            Some(fen) => fen_to_board(fen).map_err(|e| format!("Bad start FEN: {}", e))?,
+            None => (Board::start_pos(), PositionInfo::start_pos()),
+        };
+        let network_mode = options.mode.network_name();
+        let my_color = match network_mode {
+            Some("server") => Color::Black,
+            _ => options.color.unwrap_or(ColorChoice::White).resolve(),
+        };
+        // network games start their clocks once the handshake is done
+        let clock = match network_mode {
+            None => options.time.map(|control| started_clock(control, board.move_turn)),
+            Some(_) => None,
+        };
+
+        Ok(GameSession {
+            start: (board.clone(), position_info.clone()),
+            history: vec![position_key(&board, &position_info)],
+            positions: vec![(board.clone(), position_info.clone())],
+            board,
+            position_info,
+            moves: Vec::new(),
+            network_mode,
+            my_color,
+            ai: options.ai,
+            clock,
+            negotiating: network_mode.is_some(),
+            desync: None,
+            game_over: None,
+            takeback_sent: None,
+            takeback_prompt: None,
+            draw_offered: false,
+            draw_prompt: false,
+            replaying: None,
+            redo: Vec::new(),
+            pgn_path: None,
+            pgn_dir: options.pgn_dir.clone(),
+            color_choice: options.color,
+            time_choice: options.time,
+            net_writer: None,
+            net_reader: None,
+            thinking: None,
+        })
+    }
+
+    pub fn board(&self) -> &Board {
+        &self.board
+    }
+
+    pub fn position_info(&self) -> &PositionInfo {
+        &self.position_info
+    }
+
+    /// Where the game started, and where "new game" goes back to.
+    pub fn start(&self) -> &(Board, PositionInfo) {
+        &self.start
+    }
+
+    /// Every move of the game, in order. While replaying, also the ones not shown yet.
+    pub fn moves(&self) -> &[MoveRecord] {
+        &self.moves
+    }
+
+    /// "server", "client" or "uci", None on one machine.
+    pub fn network_mode(&self) -> Option<&'static str> {
+        self.network_mode
+    }
+
+    /// In network games only a guess until the colour handshake is done.
+    pub fn my_color(&self) -> Color {
+        self.my_color
+    }
+
+    /// How well the computer plays the colour that is not ours, None without it.
+    pub fn ai(&self) -> Option<Difficulty> {
+        self.ai
+    }
+
+    pub fn clock(&self) -> Option<&Clock> {
+        self.clock.as_ref()
+    }
+
+    /// A network game waiting for the colour handshake.
+    pub fn negotiating(&self) -> bool {
+        self.negotiating
+    }
+
+    /// Why the game was stopped because the boards no longer agree.
+    pub fn desync(&self) -> Option<&str> {
+        self.desync.as_deref()
+    }
+
+    pub fn game_over(&self) -> Option<&GameOver> {
+        self.game_over.as_ref()
+    }
+
+    /// Our takeback request, waiting for an answer.
+    pub fn takeback_sent(&self) -> Option<TakebackMsg> {
+        self.takeback_sent
+    }
+
+    /// The opponent's takeback request, waiting for ours.
+    pub fn takeback_prompt(&self) -> Option<TakebackMsg> {
+        self.takeback_prompt
+    }
+
+    /// We offered a draw and the opponent has not answered.
+    pub fn draw_offered(&self) -> bool {
+        self.draw_offered
+    }
+
+    /// The opponent offered a draw and waits for our answer.
+    pub fn draw_prompt(&self) -> bool {
+        self.draw_prompt
+    }
+
+    /// Talk to the opponent over `channels`. The client (and we, against an engine)
+    /// opens the colour handshake by asking for the colour it wants.
+    pub fn connect(&mut self, (writer, reader): Channels) {
+        self.net_writer = Some(writer);
+        self.net_reader = Some(reader);
+        if matches!(self.network_mode, Some("client" | "uci")) {
+            let wanted = self.color_choice.unwrap_or(ColorChoice::White);
+            println!("Asking the opponent to let us play {}", wanted.as_str());
+            self.send(ProtocolMsg::Hello(HelloMsg { color: wanted, time: self.time_choice })); // queued until the connection is up
+        }
+    }
+
+    fn send(&self, msg: ProtocolMsg) {
+        if let Some(tx) = &self.net_writer
+            && let Err(e) = tx.send(msg)
+        {
+            eprintln!("Failed to send message to network thread: {}", e);
+        }
+    }
+
+    /// Handle whatever the opponent sent and the computer found, and look at the clocks.
+    /// Front ends call this regularly. Returns whether anything changed.
+    pub fn poll(&mut self) -> bool {
+        let mut msgs = Vec::new();
+        let mut hung_up = false;
+        if let Some(rx) = &self.net_reader {
+            loop {
+                match rx.try_recv() {
+                    Ok(msg) => msgs.push(msg),
+                    Err(mpsc::TryRecvError::Empty) => break,
+                    Err(mpsc::TryRecvError::Disconnected) => {
+                        hung_up = true;
+                        break;
+                    }
+                }
+            }
+        }
+        let mut changed = false;
+        for msg in msgs {
+            changed |= self.handle(msg);
+        }
+        if hung_up {
+            changed |= self.connection_closed();
+        }
+        changed |= self.check_flag();
+        changed |= self.collect_ai_move();
+        self.start_thinking();
+        changed
+    }
+
+    /// One message from the opponent. Returns false when it was ignored.
+    pub fn handle(&mut self, msg: Result<ProtocolMsg, ProtocolError>) -> bool {
+        if self.desync.is_some() || self.game_over.is_some() {
+            return false; // nothing the opponent says matters anymore
+        }
+        match msg {
+            Ok(ProtocolMsg::Hello(h)) => self.handle_hello(h),
+            Ok(ProtocolMsg::Move(m)) if self.negotiating => {
+                // a chesstp peer without ChessHELO just plays, with the colours chesstp always
+                // had: the client White, the server Black, and no clocks
+                self.my_color = if self.network_mode == Some("server") { Color::Black } else { Color::White };
+                println!("Opponent skipped the colour handshake, playing {}", status::color_name(self.my_color));
+                self.negotiating = false;
+                return self.handle(Ok(ProtocolMsg::Move(m)));
+            }
+            Ok(ProtocolMsg::Move(m)) if self.board.move_turn == self.my_color => {
+                self.enter_desync(format!("opponent moved {} when it was our move", m.move_str), "desync");
+            }
+            Ok(ProtocolMsg::Takeback(_)) if self.negotiating => {
+                self.enter_desync("opponent asked for a takeback before colours were agreed".to_string(), "desync");
+            }
+            Ok(ProtocolMsg::Takeback(t)) => self.handle_takeback(t),
+            Ok(ProtocolMsg::Game(_)) if self.negotiating => {
+                self.enter_desync("opponent ended the game before colours were agreed".to_string(), "desync");
+            }
+            Ok(ProtocolMsg::Game(g)) => self.handle_game_action(g),
+            Ok(ProtocolMsg::Clock(_)) if self.negotiating => {
+                self.enter_desync("opponent sent clock times before colours were agreed".to_string(), "desync");
+            }
+            Ok(ProtocolMsg::Clock(c)) => self.handle_clock(c),
+            Ok(ProtocolMsg::Move(m)) => {
+                let before = self.board.clone();
+                if let Err(e) = apply_message_to_board(&mut self.board, &mut self.position_info, &m) {
+                    self.enter_desync(format!("opponent move {} failed: {}", m.move_str, e), "desync");
+                } else if let Err(e) = verify_fen(&self.board, &self.position_info, &m.fen) {
+                    self.enter_desync(format!("board differs after {}: {}", m.move_str, e), "desync");
+                } else {
+                    println!("Opponent move applied: {}", m.move_str);
+                    self.after_remote_move(&before, &m);
+                }
+            }
+            Ok(ProtocolMsg::Quit(q)) => self.finish_game(GameOver::opponent_left(&q.reason)),
+            Err(e) => {
+                self.enter_desync(format!("opponent sent a broken message: {}", e), e.quit_reason());
+            }
+        }
+        true
+    }
+
+    /// The network thread is gone, so the opponent can not answer anymore: the game ends
+    /// here unless it already did.
+    fn connection_closed(&mut self) -> bool {
+        self.net_reader = None;
+        if self.desync.is_some() || self.game_over.is_some() {
+            return false;
+        }
+        self.finish_game(GameOver::opponent_left("connection closed"));
+        true
+    }
+
+    /// Stop the game because we no longer agree with the opponent about the board.
+    fn enter_desync(&mut self, why: String, quit_reason: &str) {
+        eprintln!("Desync: {}", why);
+        if let Some(clock) = &mut self.clock {
+            clock.stop(Instant::now());
+        }
+        self.send(ProtocolMsg::Quit(QuitMsg { reason: quit_reason.to_string() }));
+        self.desync = Some(why);
+    }
+
+    fn handle_hello(&mut self, hello: HelloMsg) {
+        if !self.negotiating {
+            self.enter_desync("opponent sent a second colour handshake".to_string(), "desync");
+            return;
+        }
+        let time = match self.network_mode {
+            Some("server") => {
+                self.my_color = server_color(self.color_choice, hello.color);
+                let time = self.time_choice.or(hello.time);
+                self.send(ProtocolMsg::Hello(HelloMsg { color: opponent(self.my_color).into(), time }));
+                time
+            }
+            _ => match hello.color {
+                ColorChoice::White | ColorChoice::Black => {
+                    self.my_color = hello.color.resolve();
+                    hello.time
+                }
+                ColorChoice::Random => {
+                    self.enter_desync("server did not pick a colour for us".to_string(), "desync: bad color");
+                    return;
+                }
+            },
+        };
+        println!("Colours agreed, playing {}", status::color_name(self.my_color));
+        if let Some(control) = time {
+            println!("Playing with clocks, {}", control);
+        }
+        self.clock = time.map(|control| started_clock(control, self.board.move_turn));
+        self.negotiating = false;
+    }
+
+    /// Back to the start position, for games on one machine.
+    pub fn new_game(&mut self) {
+        println!("Starting a new game");
+        let (board, position_info) = self.start.clone();
+        self.history = vec![position_key(&board, &position_info)];
+        self.positions = vec![(board.clone(), position_info.clone())];
+        self.redo.clear();
+        self.clock = self.time_choice.map(|control| started_clock(control, board.move_turn));
+        self.board = board;
+        self.position_info = position_info;
+        self.moves.clear();
+        self.pgn_path = None;
+        self.game_over = None;
+        self.thinking = None;
+        self.replaying = None;
+    }
+
+    /// Step through a saved game instead of playing one, starting before its first move.
+    pub fn load_replay(&mut self, game: pgn::ReplayedGame) {
+        let pgn::ReplayedGame { positions, moves } = game;
+        self.start = positions[0].clone();
+        self.history = positions.iter().map(|(board, info)| position_key(board, info)).collect();
+        self.positions = positions;
+        self.moves = moves;
+        self.redo.clear();
+        self.clock = None;
+        self.thinking = None;
+        self.replaying = Some(0);
+        self.show_position(0);
+    }
+
+    /// Show the loaded game after `at` moves, after its last one when it has fewer.
+    pub fn show_position(&mut self, at: usize) {
+        if self.replaying.is_none() {
+            return;
+        }
+        let at = at.min(self.moves.len());
+        let (board, position_info) = self.positions[at].clone();
+        self.board = board;
+        self.position_info = position_info;
+        self.replaying = Some(at);
+    }
+
+    /// How many moves of the loaded game are on the board, None when playing.
+    pub fn replay_position(&self) -> Option<usize> {
+        self.replaying
+    }
+
+    /// Leaving: network games are saved and the opponent is told.
+    pub fn quit(&mut self) {
+        if self.net_writer.is_none() {
+            return; // no opponent, or we left already
+        }
+        if self.game_over.is_none() && self.pgn_dir.is_some() {
+            self.save_pgn(Some("User exited")); // a finished game was saved when it ended
+        }
+        self.send(ProtocolMsg::Quit(QuitMsg { reason: "User exited".to_string() }));
+        // dropping the Sender lets the network thread send what is queued and close the connection
+        self.net_writer = None;
+        self.net_reader = None;
+    }
+
+    /// The game is on and nobody is waiting for the colour handshake.
+    pub fn playing(&self) -> bool {
+        !self.negotiating && self.game_over.is_none() && self.desync.is_none() && self.replaying.is_none()
+    }
+
+    /// Whether the side to move is played on this side of the board.
+    pub fn my_turn(&self) -> bool {
+        (self.network_mode.is_none() && self.ai.is_none()) || self.board.move_turn == self.my_color
+    }
+
+    /// Whether a move may be played here right now.
+    pub fn can_move(&self) -> bool {
+        self.playing()
+            && self.my_turn()
+            && self.takeback_sent.is_none()
+            && self.takeback_prompt.is_none()
+            && !self.draw_prompt
+    }
+
+    /// Where the piece on `from` may go, nothing when it is not ours to move.
+    pub fn legal_targets(&self, from: Position) -> Vec<Position> {
+        if self.can_move() { rules::legal_targets(&self.board, from) } else { Vec::new() }
+    }
+
+    /// Play a move for our side. A pawn move to the last rank without `promo` gives
+    /// `MoveOk::NeedsPromotion` and changes nothing.
+    pub fn play_move(&mut self, from: Position, to: Position, promo: Option<PieceType>) -> Result<MoveOk, String> {
+        if !self.can_move() {
+            return Err("not your move".to_string());
+        }
+        let before = self.board.clone();
+        let ok = make_move(&mut self.board, &mut self.position_info, from, to, promo)?;
+        if !matches!(ok, MoveOk::NeedsPromotion) {
+            println!("Move applied locally: {:?} -> {:?}", from, to);
+            self.after_local_move(&before, from, to, promo);
+        }
+        Ok(ok)
+    }
+
+    /// The move that led to the current position.
+    pub fn last_move(&self) -> Option<(Position, Position)> {
+        let shown = self.replaying.unwrap_or(self.moves.len());
+        shown.checked_sub(1).map(|i| (self.moves[i].from, self.moves[i].to))
+    }
+
+    /// The move list as "1. e4 e5" lines.
+    pub fn move_lines(&self) -> Vec<String> {
+        let sans: Vec<String> = self.moves.iter().map(|m| m.san.clone()).collect();
+        let black_first = self.start.0.move_turn == Color::Black;
+        notation::numbered_pairs(&sans, self.start.1.fullmove_number, black_first)
+    }
+
+    /// The computer is working out its move.
+    pub fn thinking(&self) -> bool {
+        self.thinking.is_some()
+    }
+
+    /// Start the computer's search on its own thread when it is its move.
+    fn start_thinking(&mut self) {
+        let Some(difficulty) = self.ai else { return };
+        if self.my_turn() || self.thinking.is_some() || self.game_over.is_some() {
+            return;
+        }
+        let (tx, rx) = mpsc::channel();
+        let board = self.board.clone();
+        thread::spawn(move || {
+            // the receiver is gone if the move was undone meanwhile, nobody wants the answer then
+            let _ = tx.send(engine::best_move(&board, difficulty));
+        });
+        self.thinking = Some((self.moves.len(), rx));
+    }
+
+    /// Play the computer's move once the search is done.
+    fn collect_ai_move(&mut self) -> bool {
+        let Some((moves, rx)) = &self.thinking else { return false };
+        let found = match rx.try_recv() {
+            Ok(found) => found,
+            Err(mpsc::TryRecvError::Empty) => return false,
+            Err(mpsc::TryRecvError::Disconnected) => None,
+        };
+        let current = *moves == self.moves.len() && self.game_over.is_none();
+        self.thinking = None;
+        let Some((from, to, promo)) = found.filter(|_| current) else { return true };
+        let before = self.board.clone();
+        match make_move(&mut self.board, &mut self.position_info, from, to, promo) {
+            Ok(_) => {
+                println!("Computer played {:?} -> {:?}", from, to);
+                self.after_local_move(&before, from, to, promo);
+            }
+            Err(e) => eprintln!("Computer move {:?} -> {:?} failed: {}", from, to, e),
+        }
+        true
+    }
+
+    /// Record the position we just got to and work out whether the game is over.
+    fn record_position(&mut self) -> GameStatus {
+        self.history.push(position_key(&self.board, &self.position_info));
+        self.positions.push((self.board.clone(), self.position_info.clone()));
+        status::evaluate(&self.board, &self.position_info, &self.history)
+    }
+
+    /// Add a move to the move list; `before` is the board it was played on.
+    fn note_move(&mut self, before: &Board, from: Position, to: Position, promo: Option<PieceType>) {
+        let san = notation::move_to_san(before, from, to, promo)
+            .unwrap_or_else(|| format!("{}{}", notation::square_name(from), notation::square_name(to)));
+        self.moves.push(MoveRecord { from, to, promo, san });
+    }
+
+    /// Moves can only be taken back when nobody else is playing, and not in a saved game.
+    pub fn can_undo(&self) -> bool {
+        self.network_mode.is_none() && self.replaying.is_none()
+    }
+
+    /// Go back to the position before the last move. Returns the move and the position
+    /// it had led to.
+    fn pop_move(&mut self) -> Option<(MoveRecord, Board, PositionInfo)> {
+        let record = self.moves.pop()?;
+        let (board, position_info) = self.positions.pop().expect("a position for every move");
+        self.history.pop();
+
+        let (previous, previous_info) = self.positions.last().cloned().expect("the start position");
+        self.board = previous;
+        self.position_info = previous_info;
+        self.game_over = None;
+        self.clock_follow_turn();
+        println!("Took back {}", record.san);
+        Some((record, board, position_info))
+    }
+
+    /// Take back the last move, it can be played again with `redo`.
+    pub fn undo(&mut self) {
+        if !self.can_undo() {
+            return;
+        }
+        self.thinking = None;
+        if let Some(undone) = self.pop_move() {
+            self.redo.push(undone);
+        }
+        // against the computer, go back to a position where it is our move
+        if self.ai.is_some()
+            && !self.my_turn()
+            && let Some(undone) = self.pop_move()
+        {
+            self.redo.push(undone);
+        }
+    }
+
+    pub fn redo(&mut self) {
+        if !self.can_undo() {
+            return;
+        }
+        self.thinking = None;
+        self.redo_one();
+        if self.ai.is_some() && !self.my_turn() && self.game_over.is_none() {
+            self.redo_one();
+        }
+    }
+
+    fn redo_one(&mut self) {
+        let Some((record, board, position_info)) = self.redo.pop() else { return };
+        println!("Played {} again", record.san);
+        self.board = board;
+        self.position_info = position_info;
+        self.moves.push(record);
+        self.clock_follow_turn();
+        if let Some(over) = self.record_position().game_over() {
+            self.finish_game(over);
+        }
+    }
+
+    /// A network game that has started and not ended yet.
+    pub fn network_game_on(&self) -> bool {
+        self.network_mode.is_some() && self.playing()
+    }
+
+    /// Ask the opponent to take back our last move, and their answer to it if they
+    /// already played one.
+    pub fn request_takeback(&mut self) {
+        if !self.network_game_on() || self.takeback_sent.is_some() || self.takeback_prompt.is_some() {
+            return;
+        }
+        let plies = if self.board.move_turn == self.my_color { 2 } else { 1 };
+        if self.moves.len() < plies {
+            println!("No move of yours to take back");
+            return;
+        }
+        let request = TakebackMsg { action: TakebackAction::Request, plies, moves: self.moves.len() };
+        println!("Asking the opponent to take back {} move(s)", plies);
+        self.send(ProtocolMsg::Takeback(request));
+        self.takeback_sent = Some(request);
+    }
+
+    fn handle_takeback(&mut self, msg: TakebackMsg) {
+        match msg.action {
+            TakebackAction::Request => {
+                // a request that crossed one of our moves (or our own request) is about
+                // a position we already left
+                if msg.moves != self.moves.len() || self.takeback_sent.is_some() || self.takeback_prompt.is_some() {
+                    println!("Declining a takeback request that crossed a move");
+                    self.send(ProtocolMsg::Takeback(TakebackMsg { action: TakebackAction::Decline, ..msg }));
+                } else {
+                    println!("Opponent asks to take back {} move(s)", msg.plies);
+                    self.takeback_prompt = Some(msg);
+                }
+            }
+            TakebackAction::Accept | TakebackAction::Decline => {
+                let Some(request) = self.takeback_sent.take() else {
+                    self.enter_desync("opponent answered a takeback we did not ask for".to_string(), "desync: bad takeback");
+                    return;
+                };
+                if (msg.plies, msg.moves) != (request.plies, request.moves) {
+                    self.enter_desync("opponent answered a different takeback".to_string(), "desync: bad takeback");
+                } else if msg.action == TakebackAction::Decline {
+                    println!("Opponent declined the takeback");
+                } else if self.moves.len() != request.moves {
+                    self.enter_desync("opponent accepted a takeback for an old position".to_string(), "desync: bad takeback");
+                } else {
+                    for _ in 0..request.plies {
+                        self.pop_move();
+                    }
+                }
+            }
+        }
+    }
+
+    /// Our answer to the opponent's takeback request, both boards roll back on accept.
+    pub fn answer_takeback(&mut self, accept: bool) {
+        let Some(request) = self.takeback_prompt.take() else { return };
+        let action = if accept { TakebackAction::Accept } else { TakebackAction::Decline };
+        self.send(ProtocolMsg::Takeback(TakebackMsg { action, ..request }));
+        if accept {
+            for _ in 0..request.plies {
+                self.pop_move();
+            }
+        } else {
+            println!("Declined the takeback");
+        }
+    }
+
+    /// Before both sides have moved the game can be aborted instead of resigned.
+    pub fn can_abort(&self) -> bool {
+        self.moves.len() < 2
+    }
+
+    pub fn resign(&mut self) {
+        if !self.network_game_on() {
+            return;
+        }
+        let (action, over) = if self.can_abort() {
+            (GameAction::Abort, GameOver::aborted(self.my_color))
+        } else {
+            (GameAction::Resign, GameOver::resigned(self.my_color))
+        };
+        self.send(ProtocolMsg::Game(GameActionMsg { action }));
+        self.finish_game(over);
+    }
+
+    pub fn offer_draw(&mut self) {
+        if !self.network_game_on() || self.draw_offered {
+            return;
+        }
+        println!("Offering a draw");
+        self.send(ProtocolMsg::Game(GameActionMsg { action: GameAction::OfferDraw }));
+        self.draw_offered = true;
+    }
+
+    pub fn answer_draw(&mut self, accept: bool) {
+        if !std::mem::take(&mut self.draw_prompt) {
+            return;
+        }
+        let action = if accept { GameAction::AcceptDraw } else { GameAction::DeclineDraw };
+        self.send(ProtocolMsg::Game(GameActionMsg { action }));
+        if accept {
+            self.finish_game(GameOver::draw_agreed());
+        } else {
+            println!("Declined the draw");
+        }
+    }
+
+    fn handle_game_action(&mut self, msg: GameActionMsg) {
+        let them = opponent(self.my_color);
+        match msg.action {
+            GameAction::Resign => self.finish_game(GameOver::resigned(them)),
+            GameAction::Abort => self.finish_game(GameOver::aborted(them)),
+            // both offered at the same time, that is a draw
+            GameAction::OfferDraw if self.draw_offered => {
+                self.send(ProtocolMsg::Game(GameActionMsg { action: GameAction::AcceptDraw }));
+                self.finish_game(GameOver::draw_agreed());
+            }
+            GameAction::OfferDraw => {
+                println!("Opponent offers a draw");
+                self.draw_prompt = true;
+            }
+            GameAction::AcceptDraw | GameAction::DeclineDraw if !self.draw_offered => {
+                self.enter_desync("opponent answered a draw offer we did not make".to_string(), "desync: bad game action");
+            }
+            GameAction::AcceptDraw => self.finish_game(GameOver::draw_agreed()),
+            GameAction::DeclineDraw => {
+                println!("Opponent declined the draw");
+                self.draw_offered = false;
+            }
+        }
+    }
+
+    /// After a takeback or undo the clock of the side to move has to run, without increments.
+    fn clock_follow_turn(&mut self) {
+        let turn = self.board.move_turn;
+        if let Some(clock) = &mut self.clock
+            && clock.running() != Some(turn)
+        {
+            clock.start(turn, Instant::now());
+        }
+    }
+
+    /// Our clock times for the opponent.
+    fn clock_msg(&self) -> Option<ClockMsg> {
+        let clock = self.clock.as_ref()?;
+        let now = Instant::now();
+        Some(ClockMsg {
+            white_ms: clock.remaining(Color::White, now).as_millis() as u64,
+            black_ms: clock.remaining(Color::Black, now).as_millis() as u64,
+        })
+    }
+
+    /// The opponent's clock times after their move, or their flag falling.
+    fn handle_clock(&mut self, msg: ClockMsg) {
+        let Some(clock) = &mut self.clock else {
+            self.enter_desync("opponent sent clock times in a game without clocks".to_string(), "desync: bad time");
+            return;
+        };
+        let now = Instant::now();
+        clock.set(Color::White, Duration::from_millis(msg.white_ms), now);
+        clock.set(Color::Black, Duration::from_millis(msg.black_ms), now);
+        if let Some(loser) = clock.flagged(now) {
+            self.finish_game(GameOver::out_of_time(loser, &self.board));
+        }
+    }
+
+    /// End the game when a clock runs out. In network games only our own clock counts,
+    /// the opponent tells us when theirs runs out.
+    fn check_flag(&mut self) -> bool {
+        if !self.playing() {
+            return false;
+        }
+        let Some(loser) = self.clock.as_ref().and_then(|clock| clock.flagged(Instant::now())) else { return false };
+        if self.network_mode.is_some() {
+            if loser != self.my_color {
+                return false;
+            }
+            if let Some(msg) = self.clock_msg() {
+                self.send(ProtocolMsg::Clock(msg));
+            }
+        }
+        self.finish_game(GameOver::out_of_time(loser, &self.board));
+        true
+    }
+
+    fn finish_game(&mut self, over: GameOver) {
+        println!("Game over: {} ({})", over.reason, over.result);
+        if let Some(clock) = &mut self.clock {
+            clock.stop(Instant::now());
+        }
+        self.takeback_prompt = None;
+        self.draw_prompt = false;
+        self.game_over = Some(over);
+        if self.pgn_dir.is_some() {
+            self.save_pgn(None);
+        }
+    }
+
+    /// Write the game so far as PGN, into --pgn-dir or the current directory. Every save
+    /// of a game goes to the same file.
+    pub fn save_pgn(&mut self, termination: Option<&str>) {
+        let me = local_player();
+        let them = match (self.network_mode, self.ai) {
+            (Some("server"), _) => Some("Opponent (client)".to_string()),
+            (Some("client"), _) => Some("Opponent (server)".to_string()),
+            (Some(_), _) => Some("UCI engine".to_string()),
+            (None, Some(level)) => Some(format!("Computer ({})", level.as_str())),
+            (None, None) => None,
+        };
+        let (white, black) = match them {
+            None => (me.clone(), me),
+            Some(them) => match self.my_color {
+                Color::White => (me, them),
+                Color::Black => (them, me),
+            },
+        };
+        let game = pgn::PgnGame {
+            white,
+            black,
+            date: pgn::today(),
+            result: self.game_over.as_ref().map_or("0-0".to_string(), |over| over.result.clone()),
+            mode: self.network_mode.map(str::to_string),
+            termination: termination
+                .map(str::to_string)
+                .or_else(|| self.game_over.as_ref().map(|over| over.reason.clone())),
+            start_fen: board_to_fen(&self.start.0, &self.start.1),
+            moves: self.moves.iter().map(|m| m.san.clone()).collect(),
+        };
+        let saved = match &self.pgn_path {
+            Some(path) => game.save(path).map(|()| path.clone()),
+            None => game.save_new(self.pgn_dir.as_deref().unwrap_or(Path::new(".")), self.network_mode),
+        };
+        match saved {
+            Ok(path) => {
+                println!("Game saved to {}", path.display());
+                self.pgn_path = Some(path);
+            }
+            Err(e) => eprintln!("Could not save the game: {}", e),
+        }
+    }
+
+    /// Tell the opponent about a move we just made and see if it ended the game.
+    fn after_local_move(&mut self, before: &Board, from: Position, to: Position, promo: Option<PieceType>) {
+        self.note_move(before, from, to, promo);
+        self.redo.clear(); // a new move replaces whatever was undone
+        if let Some(clock) = &mut self.clock {
+            clock.press(opponent(self.board.move_turn), Instant::now());
+        }
+        let status = self.record_position();
+        if self.net_writer.is_some() {
+            let msg = board_move_to_message(from, to, promo, &self.board, &self.position_info, &status);
+            self.send(ProtocolMsg::Move(msg));
+            if let Some(msg) = self.clock_msg() {
+                self.send(ProtocolMsg::Clock(msg));
+            }
+        }
+        if let Some(over) = status.game_over() {
+            self.finish_game(over);
+        }
+    }
+
+    /// The opponent's move is on the board, end the game if it did. The opponent has to
+    /// agree with us about that, a result we do not see ourselves is a desync.
+    fn after_remote_move(&mut self, before: &Board, m: &MoveMsg) {
+        if let Ok((from, to, promo)) = parse_move_str(&m.move_str) {
+            self.note_move(before, from, to, promo);
+        }
+        // the ChessTIME right behind the move corrects this
+        if let Some(clock) = &mut self.clock {
+            clock.press(opponent(self.board.move_turn), Instant::now());
+        }
+        let status = self.record_position();
+        if m.game_state != status.game_state_str() {
+            let why = format!("opponent says {} after {}, we see {}", m.game_state, m.move_str, status.game_state_str());
+            self.enter_desync(why, "desync");
+        } else if let Some(over) = status.game_over() {
+            self.finish_game(over);
+        }
+    }
+}
+
+fn started_clock(control: TimeControl, turn: Color) -> Clock {
+    let mut clock = Clock::new(control);
+    clock.start(turn, Instant::now());
+    clock
+}
+
+/// Name for our side in saved games.
+fn local_player() -> String {
+    env::var("USER")
+        .or_else(|_| env::var("USERNAME"))
+        .unwrap_or_else(|_| "?".to_string())
+}
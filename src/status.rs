@@ -0,0 +1,184 @@
+use hermanha_chess::{Board, Color, PieceType};
+
+use crate::helper::PositionInfo;
+use crate::rules::{all_squares, has_legal_move, is_in_check, opponent};
+
+/// Whether the game is still going and, if not, why it ended.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameStatus {
+    Ongoing,
+    Checkmate { winner: Color },
+    Stalemate,
+    ThreefoldRepetition,
+    FiftyMoveRule,
+    InsufficientMaterial,
+}
+
+impl GameStatus {
+    pub fn is_over(&self) -> bool {
+        *self != GameStatus::Ongoing
+    }
+
+    /// Value for the game state field of a ChessMOVE, see the chesstp spec.
+    pub fn game_state_str(&self) -> &'static str {
+        match self {
+            GameStatus::Ongoing => "0-0",
+            GameStatus::Checkmate { winner: Color::White } => "1-0",
+            GameStatus::Checkmate { winner: Color::Black } => "0-1",
+            _ => "1/2-1/2",
+        }
+    }
+
+    pub fn game_over(&self) -> Option<GameOver> {
+        if !self.is_over() {
+            return None;
+        }
+        Some(GameOver {
+            result: self.game_state_str().to_string(),
+            reason: self.describe(),
+        })
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            GameStatus::Ongoing => "Game in progress".to_string(),
+            GameStatus::Checkmate { winner } => format!("Checkmate - {} wins", color_name(*winner)),
+            GameStatus::Stalemate => "Draw by stalemate".to_string(),
+            GameStatus::ThreefoldRepetition => "Draw by threefold repetition".to_string(),
+            GameStatus::FiftyMoveRule => "Draw by the fifty-move rule".to_string(),
+            GameStatus::InsufficientMaterial => "Draw by insufficient material".to_string(),
+        }
+    }
+}
+
+/// How a finished game ended: the chesstp/PGN result ("1-0", "0-1", "1/2-1/2", or "*" for an
+/// aborted game) and a line for the player.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameOver {
+    pub result: String,
+    pub reason: String,
+}
+
+impl GameOver {
+    pub fn resigned(loser: Color) -> Self {
+        let result = match loser {
+            Color::White => "0-1",
+            Color::Black => "1-0",
+        };
+        GameOver {
+            result: result.to_string(),
+            reason: format!("{} resigns", color_name(loser)),
+        }
+    }
+
+    pub fn draw_agreed() -> Self {
+        GameOver {
+            result: "1/2-1/2".to_string(),
+            reason: "Draw agreed".to_string(),
+        }
+    }
+
+    /// `loser`'s clock ran out. Still a draw when the other side has nothing to mate with.
+    pub fn out_of_time(loser: Color, board: &Board) -> Self {
+        let winner = opponent(loser);
+        if !has_mating_material(board, winner) {
+            return GameOver {
+                result: "1/2-1/2".to_string(),
+                reason: format!("{} ran out of time, {} can not mate", color_name(loser), color_name(winner)),
+            };
+        }
+        let result = match loser {
+            Color::White => "0-1",
+            Color::Black => "1-0",
+        };
+        GameOver {
+            result: result.to_string(),
+            reason: format!("{} ran out of time", color_name(loser)),
+        }
+    }
+
+    /// A game stopped before it really started has no result, "*" in PGN.
+    pub fn aborted(by: Color) -> Self {
+        GameOver {
+            result: "*".to_string(),
+            reason: format!("Game aborted by {}", color_name(by)),
+        }
+    }
+
+    /// The opponent quit or the connection broke before the game was decided.
+    pub fn opponent_left(why: &str) -> Self {
+        GameOver {
+            result: "*".to_string(),
+            reason: format!("Opponent left: {}", why),
+        }
+    }
+}
+
+pub fn color_name(color: Color) -> &'static str {
+    match color {
+        Color::White => "White",
+        Color::Black => "Black",
+    }
+}
+
+/// Work out the status of the position with the side in `board.move_turn` to move.
+/// `history` holds the `helper::position_key` of every position so far, this one included.
+pub fn evaluate(board: &Board, info: &PositionInfo, history: &[String]) -> GameStatus {
+    if !has_legal_move(board) {
+        return if is_in_check(board, board.move_turn) {
+            GameStatus::Checkmate { winner: opponent(board.move_turn) }
+        } else {
+            GameStatus::Stalemate
+        };
+    }
+    if let Some(current) = history.last()
+        && history.iter().filter(|&key| key == current).count() >= 3
+    {
+        return GameStatus::ThreefoldRepetition;
+    }
+    if info.halfmove_clock >= 100 {
+        return GameStatus::FiftyMoveRule;
+    }
+    if is_insufficient_material(board) {
+        return GameStatus::InsufficientMaterial;
+    }
+    GameStatus::Ongoing
+}
+
+/// Neither side can mate: bare kings, a single minor piece, or only bishops that all
+/// stand on the same colour.
+pub fn is_insufficient_material(board: &Board) -> bool {
+    let mut knights = 0;
+    let mut bishop_square_colors = Vec::new();
+    for pos in all_squares() {
+        let Some(piece) = board.get(pos) else { continue };
+        match piece.piece_type {
+            PieceType::King => {}
+            PieceType::Knight => knights += 1,
+            PieceType::Bishop => bishop_square_colors.push((pos.row + pos.col) % 2),
+            _ => return false,
+        }
+    }
+    match (knights, bishop_square_colors.len()) {
+        (0, 0) | (1, 0) => true,
+        (0, _) => bishop_square_colors.iter().all(|&c| c == bishop_square_colors[0]),
+        _ => false,
+    }
+}
+
+/// Whether `color` has more than a bare king or a king and one minor piece.
+pub fn has_mating_material(board: &Board, color: Color) -> bool {
+    let mut minors = 0;
+    for pos in all_squares() {
+        let Some(piece) = board.get(pos) else { continue };
+        if piece.color != color {
+            continue;
+        }
+        match piece.piece_type {
+            PieceType::King => {}
+            PieceType::Knight | PieceType::Bishop => minors += 1,
+            _ => return true,
+        }
+    }
+    minors >= 2
+}
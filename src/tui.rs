@@ -0,0 +1,236 @@
+// Terminal front end, for playing where there is no window (over SSH). The board is
+// printed after every change and moves are read from stdin, as E2E4 or in SAN.
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::Ordering;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hermanha_chess::{Color, MoveOk, PieceType, Position};
+
+use crate::cli::Options;
+use crate::clock::format_time;
+use crate::helper::board_to_text;
+use crate::network::{ctrl_c_flag, hang_up, spawn_opponent};
+use crate::notation::san_to_move;
+use crate::protocol::square_to_position;
+use crate::rules;
+use crate::session::GameSession;
+use crate::status;
+
+const HELP: &str = "\
+moves: E2E4 (E7E8Q to promote) or SAN like Nf3, exd5, O-O
+commands:
+  flip             turn the board around
+  save             save the game as PGN
+  takeback         ask the opponent to take back your last move
+  draw             offer a draw
+  resign           resign, or abort before both sides have moved
+  accept, decline  answer the opponent's takeback request or draw offer
+  help             show this text
+  quit             leave the game";
+
+/// How often the opponent and the clocks are looked at while waiting for input.
+const POLL: Duration = Duration::from_millis(100);
+
+/// Move list lines shown under the board.
+const MOVE_LINES: usize = 3;
+
+/// The terminal on top of a GameSession.
+struct Tui {
+    session: GameSession,
+    flipped: bool, // drawn from the other side
+}
+
+impl Tui {
+    /// "E2E4"/"e7e8q" or SAN to a move on the current board.
+    fn parse_move(&self, text: &str) -> Result<(Position, Position, Option<PieceType>), String> {
+        let coordinates = text
+            .get(0..2)
+            .and_then(square_to_position)
+            .zip(text.get(2..4).and_then(square_to_position));
+        if let Some((from, to)) = coordinates
+            && text.len() <= 5
+        {
+            let promo = match text.get(4..).map(|p| p.to_ascii_uppercase()).as_deref() {
+                None | Some("") => None,
+                Some("Q") => Some(PieceType::Queen),
+                Some("R") => Some(PieceType::Rook),
+                Some("B") => Some(PieceType::Bishop),
+                Some("N") => Some(PieceType::Knight),
+                Some(_) => return Err(format!("{} is not a move, promote with Q, R, B or N", text)),
+            };
+            if promo.is_none() && rules::is_promotion(self.session.board(), from, to) {
+                return Err(format!("add the piece to promote to, e.g. {}Q", &text[..4]));
+            }
+            return Ok((from, to, promo));
+        }
+        san_to_move(self.session.board(), text).map_err(|e| format!("{}: {}", text, e))
+    }
+
+    fn play(&mut self, text: &str) {
+        let session = &self.session;
+        if session.negotiating() {
+            println!("Waiting for the opponent to agree on colours.");
+            return;
+        }
+        if !session.playing() {
+            println!("The game is over, type quit to leave.");
+            return;
+        }
+        if session.takeback_sent().is_some() {
+            println!("Waiting for the opponent to answer the takeback.");
+            return;
+        }
+        if session.takeback_prompt().is_some() || session.draw_prompt() {
+            println!("Answer the opponent first: accept or decline.");
+            return;
+        }
+        if !session.my_turn() {
+            println!("Not your turn! Waiting for opponent.");
+            return;
+        }
+        let (from, to, promo) = match self.parse_move(text) {
+            Ok(mv) => mv,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
+        match self.session.play_move(from, to, promo) {
+            Ok(MoveOk::NeedsPromotion) => println!("add the piece to promote to"),
+            Ok(_) => {}
+            Err(e) => println!("{}: {}", text, e),
+        }
+    }
+
+    fn takeback(&mut self) {
+        if !self.session.network_game_on() || self.session.takeback_sent().is_some() {
+            println!("No takeback to ask for here.");
+            return;
+        }
+        self.session.request_takeback();
+    }
+
+    /// Accept or decline whatever the opponent asked for.
+    fn answer(&mut self, accept: bool) {
+        if self.session.takeback_prompt().is_some() {
+            self.session.answer_takeback(accept);
+        } else if self.session.draw_prompt() {
+            self.session.answer_draw(accept);
+        } else {
+            println!("Nothing to answer.");
+        }
+    }
+
+    /// One line of input. Returns false when the player wants to leave.
+    fn command(&mut self, line: &str) -> bool {
+        match line.to_ascii_lowercase().as_str() {
+            "" => {}
+            "quit" | "exit" => return false,
+            "help" | "?" => println!("{}", HELP),
+            "flip" => self.flipped = !self.flipped,
+            "save" => self.session.save_pgn(None),
+            "takeback" => self.takeback(),
+            "draw" => self.session.offer_draw(),
+            "resign" | "abort" => self.session.resign(),
+            "accept" | "yes" => self.answer(true),
+            "decline" | "no" => self.answer(false),
+            _ => self.play(line),
+        }
+        true
+    }
+
+    fn render(&self) {
+        let black_at_bottom = (self.session.my_color() == Color::Black) != self.flipped;
+        let mut out = String::from("\n");
+        out.push_str(&board_to_text(self.session.board(), black_at_bottom));
+
+        let lines = self.session.move_lines();
+        for line in &lines[lines.len().saturating_sub(MOVE_LINES)..] {
+            out.push_str(line);
+            out.push('\n');
+        }
+        if let Some(clock) = self.session.clock() {
+            let now = Instant::now();
+            out.push_str(&format!(
+                "White {}  Black {}\n",
+                format_time(clock.remaining(Color::White, now)),
+                format_time(clock.remaining(Color::Black, now))
+            ));
+        }
+
+        let turn = status::color_name(self.session.board().move_turn);
+        let state = if let Some(why) = self.session.desync() {
+            format!("Desync - game stopped: {}", why)
+        } else if let Some(over) = self.session.game_over() {
+            format!("{} ({})", over.reason, over.result)
+        } else if self.session.negotiating() {
+            "Waiting for the opponent to agree on colours".to_string()
+        } else if let Some(request) = self.session.takeback_prompt() {
+            let what = if request.plies == 1 { "their last move" } else { "their last move and your answer" };
+            format!("Opponent asks to take back {}: accept or decline?", what)
+        } else if self.session.draw_prompt() {
+            "Opponent offers a draw: accept or decline?".to_string()
+        } else if self.session.takeback_sent().is_some() {
+            "Waiting for the opponent to answer the takeback".to_string()
+        } else if self.session.my_turn() {
+            let check = if rules::is_in_check(self.session.board(), self.session.board().move_turn) { ", check" } else { "" };
+            format!("{} to move{}", turn, check)
+        } else {
+            format!("{} to move, waiting for the opponent", turn)
+        };
+        out.push_str(&state);
+        out.push_str("\n> ");
+        print!("{}", out);
+        let _ = io::stdout().flush();
+    }
+}
+
+/// Lines typed on stdin, read on their own thread so the opponent is not kept waiting.
+fn spawn_stdin_reader() -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Play in the terminal until the player quits or stdin is closed.
+pub fn run(options: &Options) -> Result<(), String> {
+    let mut tui = Tui { session: GameSession::new(options)?, flipped: false };
+    let interrupted = ctrl_c_flag();
+    let network_thread = spawn_opponent(&options.mode, options.start_fen.as_deref()).map(|(channels, thread)| {
+        tui.session.connect(channels);
+        thread
+    });
+    println!("Type moves like E2E4 or Nf3, help for commands.");
+    tui.render();
+
+    let input = spawn_stdin_reader();
+    while !interrupted.load(Ordering::SeqCst) {
+        let mut changed = tui.session.poll();
+        match input.recv_timeout(POLL) {
+            Ok(line) => {
+                if !tui.command(line.trim()) {
+                    break;
+                }
+                changed = true;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break, // stdin closed
+        }
+        if changed {
+            tui.render();
+        }
+    }
+    tui.session.quit();
+    if let Some(thread) = network_thread {
+        hang_up(thread);
+    }
+    Ok(())
+}
@@ -0,0 +1,388 @@
+// Plays a UCI engine (https://backscattering.de/chess/uci/) as the opponent. The engine
+// sits behind the same channels as a chesstp connection, so the GUI treats it like a
+// server on the other end of the wire.
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::mpsc::{Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use hermanha_chess::{Board, Color, MoveOk, PieceType, Position};
+
+use crate::clock::TimeControl;
+use crate::helper::{apply_message_to_board, board_move_to_message, board_to_fen, fen_to_board, make_move, position_key, verify_fen, PositionInfo};
+use crate::pgn::START_FEN;
+use crate::protocol::{
+    parse_move_str, position_to_square, server_color, square_to_position, ClockMsg, GameAction, GameActionMsg, HelloMsg, ProtocolError,
+    ProtocolMsg, QuitMsg, TakebackAction, TakebackMsg,
+};
+use crate::rules::opponent;
+use crate::status;
+
+/// How long an engine gets to exit after "quit" before it is killed.
+const QUIT_WAIT: Duration = Duration::from_secs(1);
+
+/// A running engine process, spoken to over its stdin and stdout.
+pub struct UciEngine {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl UciEngine {
+    /// Start `command`, the program followed by its arguments separated by spaces.
+    pub fn spawn(command: &str) -> io::Result<Self> {
+        let mut words = command.split_whitespace();
+        let program = words
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty engine command"))?;
+        let mut child = Command::new(program)
+            .args(words)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = BufReader::new(child.stdout.take().expect("stdout is piped"));
+        Ok(UciEngine { child, stdin, stdout })
+    }
+
+    fn send(&mut self, line: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{}", line)?;
+        self.stdin.flush()
+    }
+
+    /// Reads lines until one starts with `word`, which is returned.
+    fn wait_for(&mut self, word: &str) -> io::Result<String> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, format!("engine exited before {}", word)));
+            }
+            let trimmed = line.trim();
+            if trimmed.split_whitespace().next() == Some(word) {
+                return Ok(trimmed.to_string());
+            }
+        }
+    }
+
+    /// The `uci`/`isready` exchange every session starts with. Returns the engine's name.
+    pub fn handshake(&mut self) -> io::Result<String> {
+        self.send("uci")?;
+        let mut name = "UCI engine".to_string();
+        let mut line = String::new();
+        loop {
+            line.clear();
+            if self.stdout.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "engine exited before uciok"));
+            }
+            let line = line.trim();
+            if let Some(id) = line.strip_prefix("id name ") {
+                name = id.to_string();
+            } else if line == "uciok" {
+                break;
+            }
+        }
+        self.send("isready")?;
+        self.wait_for("readyok")?;
+        Ok(name)
+    }
+
+    pub fn new_game(&mut self) -> io::Result<()> {
+        self.send("ucinewgame")?;
+        self.send("isready")?;
+        self.wait_for("readyok").map(|_| ())
+    }
+
+    /// Ask for a move in the position after `moves` (UCI notation) from `start_fen`.
+    /// `go` is the rest of the go command, e.g. "movetime 1000". None when the engine
+    /// has no move to play.
+    pub fn best_move(&mut self, start_fen: &str, moves: &[String], go: &str) -> io::Result<Option<String>> {
+        let mut position = format!("position fen {}", start_fen);
+        if !moves.is_empty() {
+            position.push_str(" moves ");
+            position.push_str(&moves.join(" "));
+        }
+        self.send(&position)?;
+        self.send(&format!("go {}", go))?;
+        let line = self.wait_for("bestmove")?;
+        Ok(line.split_whitespace().nth(1).filter(|&mv| mv != "(none)" && mv != "0000").map(str::to_string))
+    }
+
+    /// Ask the engine to exit and wait for it.
+    pub fn quit(mut self) -> io::Result<()> {
+        self.stop()
+    }
+
+    /// Ask the engine to exit, kill it if it has not after QUIT_WAIT, and reap it.
+    fn stop(&mut self) -> io::Result<()> {
+        if self.child.try_wait()?.is_some() {
+            return Ok(());
+        }
+        let _ = self.send("quit"); // it may be gone already
+        let deadline = Instant::now() + QUIT_WAIT;
+        while Instant::now() < deadline {
+            if self.child.try_wait()?.is_some() {
+                return Ok(());
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        eprintln!("Engine did not quit, killing it");
+        self.child.kill()?;
+        self.child.wait().map(|_| ())
+    }
+}
+
+/// Dropping a Child leaves the process running, so an engine left behind by an error
+/// is stopped here.
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.stop();
+    }
+}
+
+/// "e7e8q" to (from, to, promotion).
+pub fn parse_uci_move(mv: &str) -> Option<(Position, Position, Option<PieceType>)> {
+    if !mv.is_ascii() || !(4..=5).contains(&mv.len()) {
+        return None;
+    }
+    let from = square_to_position(&mv[0..2])?;
+    let to = square_to_position(&mv[2..4])?;
+    let promo = match mv.get(4..5) {
+        None => None,
+        Some("q") => Some(PieceType::Queen),
+        Some("r") => Some(PieceType::Rook),
+        Some("b") => Some(PieceType::Bishop),
+        Some("n") => Some(PieceType::Knight),
+        Some(_) => return None,
+    };
+    Some((from, to, promo))
+}
+
+/// (from, to, promotion) the way UCI writes it, "e7e8q".
+pub fn uci_move(from: Position, to: Position, promo: Option<PieceType>) -> String {
+    let promo = match promo {
+        Some(PieceType::Queen) => "q",
+        Some(PieceType::Rook) => "r",
+        Some(PieceType::Bishop) => "b",
+        Some(PieceType::Knight) => "n",
+        _ => "",
+    };
+    format!("{}{}{}", position_to_square(from), position_to_square(to), promo).to_lowercase()
+}
+
+/// The game as the engine's side of the connection sees it.
+struct EngineGame {
+    start_fen: String,
+    board: Board,
+    info: PositionInfo,
+    history: Vec<String>, // position_key of every position, for threefold repetition
+    moves: Vec<String>, // UCI notation
+    color: Option<Color>, // None until the handshake is done
+    time: Option<TimeControl>,
+    clock_ms: [u64; 2], // white, black, as of the last ChessTIME
+    waiting_for_clock: bool, // the opponent's times follow their move
+    over: bool,
+}
+
+impl EngineGame {
+    fn new(start_fen: &str) -> Result<Self, String> {
+        let (board, info) = fen_to_board(start_fen)?;
+        Ok(EngineGame {
+            start_fen: board_to_fen(&board, &info), // the engine needs all six fields, a --fen may leave some out
+            history: vec![position_key(&board, &info)],
+            board,
+            info,
+            moves: Vec::new(),
+            color: None,
+            time: None,
+            clock_ms: [0; 2],
+            waiting_for_clock: false,
+            over: false,
+        })
+    }
+
+    fn record_position(&mut self) -> status::GameStatus {
+        self.history.push(position_key(&self.board, &self.info));
+        let status = status::evaluate(&self.board, &self.info, &self.history);
+        self.over = status.is_over();
+        status
+    }
+
+    /// Go back `plies` moves by playing the rest again from the start.
+    fn take_back(&mut self, plies: usize) -> Result<(), String> {
+        let keep = self.moves.len() - plies;
+        let moves: Vec<String> = self.moves.drain(..).take(keep).collect();
+        let (board, info) = fen_to_board(&self.start_fen)?;
+        self.board = board;
+        self.info = info;
+        self.history = vec![position_key(&self.board, &self.info)];
+        for mv in moves {
+            let (from, to, promo) = parse_uci_move(&mv).ok_or_else(|| format!("bad move {}", mv))?;
+            make_move(&mut self.board, &mut self.info, from, to, promo)?;
+            self.record_position();
+            self.moves.push(mv);
+        }
+        self.over = false;
+        Ok(())
+    }
+
+    fn engine_to_move(&self) -> bool {
+        self.color == Some(self.board.move_turn) && !self.over && !self.waiting_for_clock
+    }
+
+    /// What follows "go": the clock times in timed games, a fixed time per move otherwise.
+    fn go_command(&self, movetime: Duration) -> String {
+        match self.time {
+            Some(control) => {
+                let inc = control.increment.as_millis();
+                format!(
+                    "wtime {} btime {} winc {} binc {}",
+                    self.clock_ms[0], self.clock_ms[1], inc, inc
+                )
+            }
+            None => format!("movetime {}", movetime.as_millis()),
+        }
+    }
+}
+
+fn clock_index(color: Color) -> usize {
+    match color {
+        Color::White => 0,
+        Color::Black => 1,
+    }
+}
+
+/// Run `command` as the opponent: answers the GUI's handshake like a server, plays the
+/// GUI's moves on the engine's board and sends back the engine's replies. Without
+/// clocks the engine gets `movetime` per move.
+pub fn start_engine_with_channel(
+    command: &str,
+    start_fen: Option<&str>,
+    movetime: Duration,
+    rx: Receiver<ProtocolMsg>,
+    tx_to_gui: Sender<Result<ProtocolMsg, ProtocolError>>,
+) -> io::Result<()> {
+    let mut engine = UciEngine::spawn(command)?;
+    let name = engine.handshake()?;
+    println!("Engine {} is ready", name);
+
+    let mut game = EngineGame::new(start_fen.unwrap_or(START_FEN))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    let send = |msg: ProtocolMsg| tx_to_gui.send(Ok(msg)).is_ok();
+    let desync = |why: String| {
+        eprintln!("Engine adapter: {}", why);
+        send(ProtocolMsg::Quit(QuitMsg { reason: "desync".to_string() }));
+    };
+
+    while let Ok(msg) = rx.recv() {
+        match msg {
+            ProtocolMsg::Hello(hello) => {
+                let color = server_color(None, hello.color);
+                game.color = Some(color);
+                game.time = hello.time;
+                if let Some(control) = hello.time {
+                    game.clock_ms = [control.base.as_millis() as u64; 2];
+                }
+                engine.new_game()?;
+                send(ProtocolMsg::Hello(HelloMsg { color: opponent(color).into(), time: hello.time }));
+            }
+            ProtocolMsg::Move(m) => {
+                if let Err(e) = apply_message_to_board(&mut game.board, &mut game.info, &m) {
+                    desync(format!("opponent move {} failed: {}", m.move_str, e));
+                    break;
+                }
+                if let Err(e) = verify_fen(&game.board, &game.info, &m.fen) {
+                    desync(format!("board differs after {}: {}", m.move_str, e));
+                    break;
+                }
+                let (from, to, promo) = parse_move_str(&m.move_str).expect("move was applied");
+                game.moves.push(uci_move(from, to, promo));
+                game.record_position();
+                game.waiting_for_clock = game.time.is_some();
+            }
+            ProtocolMsg::Clock(c) => {
+                game.clock_ms = [c.white_ms, c.black_ms];
+                game.waiting_for_clock = false;
+                if c.white_ms == 0 || c.black_ms == 0 {
+                    game.over = true;
+                }
+            }
+            ProtocolMsg::Takeback(t) if t.action == TakebackAction::Request => {
+                // the engine answers at once, so a request can only be for the current position
+                let action = if t.moves == game.moves.len() && t.plies <= game.moves.len() {
+                    match game.take_back(t.plies) {
+                        Ok(()) => TakebackAction::Accept,
+                        Err(e) => {
+                            desync(format!("takeback failed: {}", e));
+                            break;
+                        }
+                    }
+                } else {
+                    TakebackAction::Decline
+                };
+                send(ProtocolMsg::Takeback(TakebackMsg { action, ..t }));
+            }
+            ProtocolMsg::Takeback(_) => {} // we never ask for one
+            ProtocolMsg::Game(g) => match g.action {
+                GameAction::OfferDraw => {
+                    send(ProtocolMsg::Game(GameActionMsg { action: GameAction::DeclineDraw }));
+                }
+                GameAction::Resign | GameAction::Abort => game.over = true,
+                GameAction::AcceptDraw | GameAction::DeclineDraw => {} // we never offer one
+            },
+            ProtocolMsg::Quit(q) => {
+                println!("Opponent quit: {}", q.reason);
+                break;
+            }
+        }
+
+        if !game.engine_to_move() {
+            continue;
+        }
+        let started = Instant::now();
+        let Some(best) = engine.best_move(&game.start_fen, &game.moves, &game.go_command(movetime))? else {
+            desync("engine has no move although the game is on".to_string());
+            break;
+        };
+        let color = game.board.move_turn;
+
+        if let Some(control) = game.time {
+            let spent = started.elapsed().as_millis() as u64;
+            let left = game.clock_ms[clock_index(color)].saturating_sub(spent);
+            game.clock_ms[clock_index(color)] = if left == 0 { 0 } else { left + control.increment.as_millis() as u64 };
+            if left == 0 {
+                // out of time, tell the opponent instead of moving
+                let [white_ms, black_ms] = game.clock_ms;
+                send(ProtocolMsg::Clock(ClockMsg { white_ms, black_ms }));
+                game.over = true;
+                continue;
+            }
+        }
+
+        let Some((from, to, promo)) = parse_uci_move(&best) else {
+            desync(format!("engine played {:?}, which is not a move", best));
+            break;
+        };
+        match make_move(&mut game.board, &mut game.info, from, to, promo) {
+            Ok(MoveOk::NeedsPromotion) => {
+                desync(format!("engine move {} does not say what to promote to", best));
+                break;
+            }
+            Ok(_) => {}
+            Err(e) => {
+                desync(format!("engine move {} failed: {}", best, e));
+                break;
+            }
+        }
+        println!("Engine played {}", best);
+        game.moves.push(best);
+        let status = game.record_position();
+        send(ProtocolMsg::Move(board_move_to_message(from, to, promo, &game.board, &game.info, &status)));
+        if game.time.is_some() {
+            let [white_ms, black_ms] = game.clock_ms;
+            send(ProtocolMsg::Clock(ClockMsg { white_ms, black_ms }));
+        }
+    }
+    engine.quit()
+}
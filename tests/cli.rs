@@ -0,0 +1,143 @@
+use std::time::Duration;
+
+use eahla_gui::cli::{parse_args, CliError, Mode, Options, DEFAULT_ADDR, DEFAULT_MOVETIME};
+use eahla_gui::engine::Difficulty;
+use eahla_gui::protocol::ColorChoice;
+
+fn parse(args: &[&str]) -> Result<Options, CliError> {
+    let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    parse_args(&args)
+}
+
+#[test]
+fn no_arguments_is_local_play() {
+    assert_eq!(parse(&[]).unwrap().mode, Mode::Local);
+}
+
+#[test]
+fn modes_default_to_localhost() {
+    assert_eq!(parse(&["server"]).unwrap().mode, Mode::Server { bind: DEFAULT_ADDR.to_string() });
+    assert_eq!(parse(&["client"]).unwrap().mode, Mode::Client { connect: DEFAULT_ADDR.to_string() });
+}
+
+#[test]
+fn custom_addresses() {
+    assert_eq!(
+        parse(&["server", "--bind", "0.0.0.0:7000"]).unwrap().mode,
+        Mode::Server { bind: "0.0.0.0:7000".to_string() }
+    );
+    assert_eq!(
+        parse(&["client", "--connect", "chess.example.com:7000"]).unwrap().mode,
+        Mode::Client { connect: "chess.example.com:7000".to_string() }
+    );
+    // flags may come before the mode
+    assert_eq!(
+        parse(&["--connect", "[::1]:7000", "client"]).unwrap().mode,
+        Mode::Client { connect: "[::1]:7000".to_string() }
+    );
+}
+
+#[test]
+fn fen_option() {
+    let options = parse(&["--fen", "8/8/8/8/8/8/8/K6k w - - 0 1"]).unwrap();
+    assert_eq!(options.start_fen.as_deref(), Some("8/8/8/8/8/8/8/K6k w - - 0 1"));
+}
+
+#[test]
+fn color_option() {
+    assert_eq!(parse(&["server"]).unwrap().color, None);
+    assert_eq!(parse(&["server", "--color", "white"]).unwrap().color, Some(ColorChoice::White));
+    assert_eq!(parse(&["--color", "random", "client"]).unwrap().color, Some(ColorChoice::Random));
+}
+
+#[test]
+fn invalid_arguments() {
+    assert_eq!(parse(&["--help"]), Err(CliError::Help));
+    for args in [
+        &["serve"][..],
+        &["server", "--connect", "127.0.0.1:7000"],
+        &["client", "--bind", "127.0.0.1:7000"],
+        &["--bind", "127.0.0.1:7000"],
+        &["server", "--bind"],
+        &["server", "--bind", "localhost"],
+        &["server", "--bind", ":7000"],
+        &["server", "--bind", "localhost:70000"],
+        &["server", "--bind", "localhost:0"],
+        &["server", "client"],
+        &["--frobnicate"],
+        &["client", "--color", "red"],
+        &["client", "--color"],
+    ] {
+        assert!(matches!(parse(args), Err(CliError::Invalid(_))), "{:?} should be rejected", args);
+    }
+}
+
+#[test]
+fn replay_takes_a_file() {
+    assert_eq!(
+        parse(&["replay", "game.pgn"]).unwrap().mode,
+        Mode::Replay { file: "game.pgn".to_string() }
+    );
+    assert!(matches!(parse(&["replay"]), Err(CliError::Invalid(_))));
+    assert!(matches!(parse(&["replay", "a.pgn", "b.pgn"]), Err(CliError::Invalid(_))));
+    assert!(matches!(parse(&["replay", "a.pgn", "--fen", "8/8/8/8/8/8/8/K6k"]), Err(CliError::Invalid(_))));
+    assert!(matches!(parse(&["replay", "--bind", "0.0.0.0:7000", "a.pgn"]), Err(CliError::Invalid(_))));
+}
+
+#[test]
+fn time_control() {
+    let options = parse(&["server", "--time", "5+3"]).unwrap();
+    assert_eq!(options.time.unwrap().to_string(), "5+3");
+    assert_eq!(parse(&[]).unwrap().time, None);
+    assert!(matches!(parse(&["--time", "blitz"]), Err(CliError::Invalid(_))));
+}
+
+#[test]
+fn computer_opponent() {
+    assert_eq!(parse(&[]).unwrap().ai, None);
+    assert_eq!(parse(&["--ai"]).unwrap().ai, Some(Difficulty::Medium));
+    let options = parse(&["--ai", "--level", "hard", "--color", "black"]).unwrap();
+    assert_eq!(options.ai, Some(Difficulty::Hard));
+    for args in [
+        &["--level", "easy"][..],
+        &["--ai", "--level", "impossible"],
+        &["server", "--ai"],
+        &["replay", "a.pgn", "--ai"],
+    ] {
+        assert!(matches!(parse(args), Err(CliError::Invalid(_))), "{:?} should be rejected", args);
+    }
+}
+
+#[test]
+fn uci_engine() {
+    assert_eq!(
+        parse(&["uci", "stockfish -q", "--movetime", "250"]).unwrap().mode,
+        Mode::Uci { engine: "stockfish -q".to_string(), movetime: Duration::from_millis(250) }
+    );
+    assert_eq!(
+        parse(&["uci", "stockfish"]).unwrap().mode,
+        Mode::Uci { engine: "stockfish".to_string(), movetime: DEFAULT_MOVETIME }
+    );
+    assert_eq!(parse(&["uci", "stockfish"]).unwrap().mode.network_name(), Some("uci"));
+    for args in [&["uci"][..], &["--movetime", "100"], &["uci", "sf", "--movetime", "soon"], &["uci", "sf", "--ai"]] {
+        assert!(matches!(parse(args), Err(CliError::Invalid(_))), "{:?} should be rejected", args);
+    }
+}
+
+#[test]
+fn terminal_front_end() {
+    assert!(!parse(&[]).unwrap().tui);
+    let options = parse(&["client", "--tui"]).unwrap();
+    assert!(options.tui);
+    assert_eq!(options.mode, Mode::Client { connect: DEFAULT_ADDR.to_string() });
+    assert!(matches!(parse(&["replay", "a.pgn", "--tui"]), Err(CliError::Invalid(_))));
+    assert!(parse(&["--ai", "--tui"]).unwrap().tui);
+}
+
+#[test]
+fn pgn_directory() {
+    assert_eq!(parse(&[]).unwrap().pgn_dir, None);
+    let options = parse(&["server", "--pgn-dir", "games"]).unwrap();
+    assert_eq!(options.pgn_dir, Some("games".into()));
+    assert!(matches!(parse(&["--pgn-dir"]), Err(CliError::Invalid(_))));
+}
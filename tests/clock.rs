@@ -0,0 +1,66 @@
+use std::time::{Duration, Instant};
+
+use eahla_gui::clock::{format_time, Clock, TimeControl};
+use hermanha_chess::Color;
+
+fn secs(s: u64) -> Duration {
+    Duration::from_secs(s)
+}
+
+#[test]
+fn time_controls() {
+    let blitz = TimeControl::parse("5+3").unwrap();
+    assert_eq!(blitz.base, secs(300));
+    assert_eq!(blitz.increment, secs(3));
+    assert_eq!(blitz.to_string(), "5+3");
+
+    assert_eq!(TimeControl::parse("10").unwrap().increment, Duration::ZERO);
+    assert_eq!(TimeControl::parse("0.5+1").unwrap().base, secs(30));
+    assert_eq!(TimeControl::parse("0.5+1").unwrap().to_string(), "0.5+1");
+    for bad in ["", "5+", "+3", "five", "0+3", "-1+0", "5+3.5"] {
+        assert!(TimeControl::parse(bad).is_err(), "{}", bad);
+    }
+}
+
+#[test]
+fn clocks_run_one_at_a_time() {
+    let t0 = Instant::now();
+    let mut clock = Clock::new(TimeControl::parse("1+2").unwrap());
+    clock.start(Color::White, t0);
+
+    assert_eq!(clock.remaining(Color::White, t0 + secs(10)), secs(50));
+    assert_eq!(clock.remaining(Color::Black, t0 + secs(10)), secs(60));
+
+    // white moves after 10s and gets the increment, black's clock starts
+    clock.press(Color::White, t0 + secs(10));
+    assert_eq!(clock.running(), Some(Color::Black));
+    assert_eq!(clock.remaining(Color::White, t0 + secs(30)), secs(52));
+    assert_eq!(clock.remaining(Color::Black, t0 + secs(30)), secs(40));
+
+    // the opponent's times replace ours, a running clock keeps running from then
+    clock.set(Color::Black, secs(45), t0 + secs(30));
+    assert_eq!(clock.remaining(Color::Black, t0 + secs(35)), secs(40));
+
+    clock.stop(t0 + secs(35));
+    assert_eq!(clock.running(), None);
+    assert_eq!(clock.remaining(Color::Black, t0 + secs(500)), secs(40));
+}
+
+#[test]
+fn flag_fall() {
+    let t0 = Instant::now();
+    let mut clock = Clock::new(TimeControl::parse("1").unwrap());
+    clock.start(Color::Black, t0);
+    assert_eq!(clock.flagged(t0 + secs(59)), None);
+    assert_eq!(clock.flagged(t0 + secs(60)), Some(Color::Black));
+    assert_eq!(clock.remaining(Color::Black, t0 + secs(90)), Duration::ZERO);
+}
+
+#[test]
+fn clock_faces() {
+    assert_eq!(format_time(secs(300)), "5:00");
+    assert_eq!(format_time(secs(61)), "1:01");
+    assert_eq!(format_time(secs(10)), "0:10");
+    assert_eq!(format_time(Duration::from_millis(9_870)), "9.8");
+    assert_eq!(format_time(Duration::ZERO), "0.0");
+}
@@ -0,0 +1,43 @@
+use eahla_gui::engine::{best_move, evaluate, Difficulty};
+use eahla_gui::helper::fen_to_board;
+use eahla_gui::pgn::START_FEN;
+use hermanha_chess::{PieceType, Position};
+
+#[test]
+fn start_position_is_level() {
+    let (board, _) = fen_to_board(START_FEN).unwrap();
+    assert_eq!(evaluate(&board), 0);
+    let (board, _) = fen_to_board("rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap();
+    assert!(evaluate(&board) > 800);
+}
+
+#[test]
+fn takes_a_hanging_queen() {
+    let (board, _) = fen_to_board("4k3/8/8/3q4/8/8/3R4/4K3 w - - 0 1").unwrap();
+    for difficulty in [Difficulty::Easy, Difficulty::Medium] {
+        assert_eq!(best_move(&board, difficulty), Some((Position::new(1, 3), Position::new(4, 3), None)));
+    }
+}
+
+#[test]
+fn finds_mate_in_one() {
+    // Ra8 mates, which beats winning the knight on d1
+    let (board, _) = fen_to_board("6k1/5ppp/8/8/8/8/8/R2n2K1 w - - 0 1").unwrap();
+    assert_eq!(best_move(&board, Difficulty::Medium), Some((Position::new(0, 0), Position::new(7, 0), None)));
+}
+
+#[test]
+fn promotes_and_knows_when_it_is_over() {
+    let (board, _) = fen_to_board("8/P7/8/8/8/8/k7/4K3 w - - 0 1").unwrap();
+    assert_eq!(
+        best_move(&board, Difficulty::Easy),
+        Some((Position::new(6, 0), Position::new(7, 0), Some(PieceType::Queen)))
+    );
+
+    let (mated, _) = fen_to_board("R5k1/5ppp/8/8/8/8/8/6K1 b - - 0 1").unwrap();
+    assert_eq!(best_move(&mated, Difficulty::Hard), None);
+
+    assert_eq!(Difficulty::parse("hard"), Some(Difficulty::Hard));
+    assert_eq!(Difficulty::parse("grandmaster"), None);
+    assert_eq!(Difficulty::Medium.as_str(), "medium");
+}
@@ -0,0 +1,117 @@
+use eahla_gui::helper::{board_to_fen, board_to_text, fen_to_board, make_move, verify_fen, PositionInfo};
+use eahla_gui::protocol::square_to_position;
+use hermanha_chess::Board;
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+fn play(board: &mut Board, info: &mut PositionInfo, moves: &[&str]) {
+    for mv in moves {
+        let from = square_to_position(&mv[0..2]).unwrap();
+        let to = square_to_position(&mv[2..4]).unwrap();
+        make_move(board, info, from, to, None).unwrap();
+    }
+}
+
+#[test]
+fn start_position() {
+    let board = Board::start_pos();
+    assert_eq!(board_to_fen(&board, &PositionInfo::start_pos()), START_FEN);
+}
+
+#[test]
+fn fen_round_trip() {
+    for fen in [
+        START_FEN,
+        "rnbqkbnr/pp1ppppp/8/2p5/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2",
+        "r3k2r/8/8/3pP3/8/8/8/R3K2R w Kq d6 0 30",
+        "8/8/8/8/8/8/8/K6k b - - 99 120",
+    ] {
+        let (board, info) = fen_to_board(fen).unwrap();
+        assert_eq!(board_to_fen(&board, &info), fen);
+    }
+}
+
+#[test]
+fn placement_only_fen_gets_defaults() {
+    let (board, info) = fen_to_board("8/8/8/8/8/8/8/K6k").unwrap();
+    assert_eq!(board_to_fen(&board, &info), "8/8/8/8/8/8/8/K6k w - - 0 1");
+}
+
+#[test]
+fn moves_update_every_field() {
+    let mut board = Board::start_pos();
+    let mut info = PositionInfo::start_pos();
+
+    play(&mut board, &mut info, &["E2E4"]);
+    assert_eq!(
+        board_to_fen(&board, &info),
+        "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1"
+    );
+
+    play(&mut board, &mut info, &["G8F6", "E1E2", "H8G8"]);
+    assert_eq!(
+        board_to_fen(&board, &info),
+        "rnbqkbr1/pppppppp/5n2/8/4P3/8/PPPPKPPP/RNBQ1BNR w q - 3 3"
+    );
+}
+
+#[test]
+fn fen_sets_en_passant_and_castling() {
+    let legal = |fen: &str, mv: &str| {
+        let (mut board, mut info) = fen_to_board(fen).unwrap();
+        let from = square_to_position(&mv[0..2]).unwrap();
+        let to = square_to_position(&mv[2..4]).unwrap();
+        make_move(&mut board, &mut info, from, to, None).is_ok()
+    };
+    assert!(legal("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "E5D6"));
+    assert!(!legal("4k3/8/8/3pP3/8/8/8/4K3 w - - 0 1", "E5D6"));
+    assert!(legal("4k3/8/8/8/4Pp2/8/8/4K3 b - e3 0 1", "F4E3"));
+
+    // king and rooks at home, but only the rights the FEN gives
+    assert!(!legal("r3k2r/8/8/8/8/8/8/R3K2R w - - 0 1", "E1G1"));
+    assert!(legal("r3k2r/8/8/8/8/8/8/R3K2R w Kq - 0 1", "E1G1"));
+    assert!(!legal("r3k2r/8/8/8/8/8/8/R3K2R w Kq - 0 1", "E1C1"));
+    assert!(legal("r3k2r/8/8/8/8/8/8/R3K2R b Kq - 0 1", "E8C8"));
+    assert!(!legal("r3k2r/8/8/8/8/8/8/R3K2R b Kq - 0 1", "E8G8"));
+
+    // rights and en passant squares the board can not have
+    assert!(fen_to_board("4k3/8/8/8/8/8/8/4K3 w K - 0 1").is_err());
+    assert!(fen_to_board("4k3/8/8/3pP3/8/8/8/4K3 w - e6 0 1").is_err());
+    assert!(fen_to_board("4k3/8/8/3pP3/8/8/8/4K3 b - d6 0 1").is_err());
+}
+
+#[test]
+fn bad_fens_are_rejected() {
+    assert!(fen_to_board("").is_err());
+    assert!(fen_to_board("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP").is_err());
+    assert!(fen_to_board("rnbqkbnr/pppppppp/9/8/8/8/PPPPPPPP/RNBQKBNR").is_err());
+    assert!(fen_to_board("rnbqkbnr/ppppxppp/8/8/8/8/PPPPPPPP/RNBQKBNR").is_err());
+    assert!(fen_to_board(&START_FEN.replace(" w ", " x ")).is_err());
+    assert!(fen_to_board(&START_FEN.replace("KQkq", "KQkz")).is_err());
+    assert!(fen_to_board(&START_FEN.replace(" - ", " e4 ")).is_err());
+    assert!(fen_to_board(&START_FEN.replace(" 0 1", " zero 1")).is_err());
+}
+
+#[test]
+fn verify_against_peer_fen() {
+    let mut board = Board::start_pos();
+    let mut info = PositionInfo::start_pos();
+    play(&mut board, &mut info, &["E2E4"]);
+
+    assert!(verify_fen(&board, &info, "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR").is_ok());
+    assert!(verify_fen(&board, &info, "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").is_ok());
+    assert!(verify_fen(&board, &info, "rnbqkbnr/pppppppp/8/8/3P4/8/PPP1PPPP/RNBQKBNR b KQkq d3 0 1").is_err());
+    assert!(verify_fen(&board, &info, "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e3 0 1").is_err());
+    assert!(verify_fen(&board, &info, "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b Kkq e3 0 1").is_err());
+}
+
+#[test]
+fn board_as_text_from_either_side() {
+    let (board, _) = fen_to_board("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1").unwrap();
+    let white = board_to_text(&board, false);
+    assert!(white.starts_with("8 . . . . k . . . \n"));
+    assert!(white.ends_with("1 R . . . K . . . \n  A B C D E F G H\n"));
+    let black = board_to_text(&board, true);
+    assert!(black.starts_with("1 . . . K . . . R \n"));
+    assert!(black.ends_with("8 . . . k . . . . \n  H G F E D C B A\n"));
+}
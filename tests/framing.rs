@@ -0,0 +1,152 @@
+use std::io::{self, Read};
+
+use eahla_gui::network::{write_frame, FrameDecoder, FrameError, FrameReader, FRAME_LEN};
+use eahla_gui::protocol::{MoveMsg, QuitMsg};
+
+fn move_frame(move_str: &str) -> String {
+    MoveMsg {
+        move_str: move_str.to_string(),
+        game_state: "0-0".to_string(),
+        fen: "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR".to_string(),
+    }
+    .serialize()
+}
+
+fn quit_frame(reason: &str) -> String {
+    QuitMsg { reason: reason.to_string() }.serialize()
+}
+
+/// Hands out the stream in fixed-size chunks, like a slow or fragmenting socket.
+struct Chunked {
+    data: Vec<u8>,
+    pos: usize,
+    chunk: usize,
+}
+
+impl Read for Chunked {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.chunk.min(buf.len()).min(self.data.len() - self.pos);
+        buf[..n].copy_from_slice(&self.data[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+fn read_all(data: Vec<u8>, chunk: usize) -> Vec<Result<Option<String>, FrameError>> {
+    let mut reader = FrameReader::new(Chunked { data, pos: 0, chunk });
+    let mut out = Vec::new();
+    loop {
+        let r = reader.read_frame();
+        let done = matches!(r, Ok(None) | Err(FrameError::Truncated(_)));
+        out.push(r);
+        if done {
+            return out;
+        }
+    }
+}
+
+#[test]
+fn serialized_messages_are_one_frame() {
+    assert_eq!(move_frame("E2E40").len(), FRAME_LEN);
+    assert_eq!(quit_frame("desync").len(), FRAME_LEN);
+}
+
+#[test]
+fn byte_by_byte_stream() {
+    let frames = [move_frame("E2E40"), quit_frame("user quit")];
+    let results = read_all(frames.concat().into_bytes(), 1);
+
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].as_ref().unwrap().as_deref(), Some(frames[0].as_str()));
+    assert_eq!(results[1].as_ref().unwrap().as_deref(), Some(frames[1].as_str()));
+    assert!(matches!(results[2], Ok(None)));
+}
+
+#[test]
+fn several_frames_in_one_read() {
+    let frames = [move_frame("E2E40"), move_frame("E7E50"), quit_frame("bye")];
+    let results = read_all(frames.concat().into_bytes(), 4096);
+
+    let got: Vec<String> = results.into_iter().filter_map(|r| r.unwrap()).collect();
+    assert_eq!(got, frames);
+}
+
+#[test]
+fn frames_split_across_reads() {
+    let frames = [move_frame("G1F30"), move_frame("B8C60")];
+    // 100 does not divide 128, so every frame boundary lands mid-read
+    let results = read_all(frames.concat().into_bytes(), 100);
+
+    let got: Vec<String> = results.into_iter().filter_map(|r| r.unwrap()).collect();
+    assert_eq!(got, frames);
+}
+
+#[test]
+fn decoder_waits_for_full_frame() {
+    let frame = move_frame("E2E40");
+    let mut decoder = FrameDecoder::new();
+
+    decoder.push(&frame.as_bytes()[..127]);
+    assert!(decoder.next_frame().is_none());
+    assert_eq!(decoder.pending(), 127);
+
+    decoder.push(&frame.as_bytes()[127..]);
+    assert_eq!(decoder.next_frame().unwrap().unwrap(), frame);
+    assert!(decoder.next_frame().is_none());
+}
+
+#[test]
+fn oversized_frame_is_reported_and_stream_recovers() {
+    let mut data = move_frame("E2E40").into_bytes();
+    data.extend_from_slice(b"0000"); // peer sent 132 bytes
+    data.extend_from_slice(quit_frame("bye").as_bytes());
+
+    let results = read_all(data, 7);
+
+    assert!(matches!(results[0], Ok(Some(_))));
+    assert!(matches!(results[1], Err(FrameError::Malformed(_))));
+    assert_eq!(results[2].as_ref().unwrap().as_deref(), Some(quit_frame("bye").as_str()));
+    assert!(matches!(results[3], Ok(None)));
+}
+
+#[test]
+fn garbage_before_header_is_skipped() {
+    let mut decoder = FrameDecoder::new();
+    decoder.push(b"hello");
+    decoder.push(quit_frame("bye").as_bytes());
+
+    assert!(matches!(decoder.next_frame(), Some(Err(FrameError::Malformed(_)))));
+    assert_eq!(decoder.next_frame().unwrap().unwrap(), quit_frame("bye"));
+}
+
+#[test]
+fn non_ascii_frame_is_malformed() {
+    let mut bytes = quit_frame("bye").into_bytes();
+    bytes[20] = 0xff;
+    let mut decoder = FrameDecoder::new();
+    decoder.push(&bytes);
+
+    assert!(matches!(decoder.next_frame(), Some(Err(FrameError::Malformed(_)))));
+    assert!(decoder.next_frame().is_none());
+}
+
+#[test]
+fn truncated_stream_is_reported() {
+    let frame = move_frame("E2E40");
+    let results = read_all(frame.as_bytes()[..60].to_vec(), 16);
+
+    assert!(matches!(results[0], Err(FrameError::Truncated(60))));
+}
+
+#[test]
+fn write_frame_rejects_wrong_length() {
+    let mut out = Vec::new();
+    let long = "ChessQUIT:".to_string() + &"x".repeat(200);
+
+    assert!(matches!(write_frame(&mut out, &long), Err(FrameError::WrongLength(210))));
+    assert!(matches!(write_frame(&mut out, "ChessQUIT:bye"), Err(FrameError::WrongLength(13))));
+    assert!(out.is_empty());
+
+    write_frame(&mut out, &quit_frame("bye")).unwrap();
+    assert_eq!(out.len(), FRAME_LEN);
+}
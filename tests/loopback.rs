@@ -0,0 +1,144 @@
+use std::io;
+use std::net::TcpListener;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use eahla_gui::helper::{
+    apply_message_to_board, board_move_to_message, board_to_fen, fen_to_board, make_move, verify_fen, PositionInfo,
+};
+use eahla_gui::network::{start_client_with_channel, start_server_with_channel};
+use eahla_gui::notation::san_to_move;
+use eahla_gui::protocol::{ProtocolError, ProtocolMsg, QuitMsg};
+use eahla_gui::status;
+use hermanha_chess::{Board, Color};
+
+const WAIT: Duration = Duration::from_secs(5);
+
+type Network = (Sender<ProtocolMsg>, Receiver<Result<ProtocolMsg, ProtocolError>>, JoinHandle<io::Result<()>>);
+
+/// One end of the connection as a front end sees it: its own board, both channels
+/// and the network thread.
+struct Side {
+    board: Board,
+    info: PositionInfo,
+    tx: Sender<ProtocolMsg>,
+    rx: Receiver<Result<ProtocolMsg, ProtocolError>>,
+    thread: JoinHandle<io::Result<()>>,
+}
+
+impl Side {
+    fn new(start_fen: Option<&str>, (tx, rx, thread): Network) -> Self {
+        let (board, info) = match start_fen {
+            Some(fen) => fen_to_board(fen).unwrap(),
+            None => (Board::start_pos(), PositionInfo::start_pos()),
+        };
+        Side { board, info, tx, rx, thread }
+    }
+
+    fn recv(&self) -> ProtocolMsg {
+        self.rx.recv_timeout(WAIT).expect("nothing arrived from the peer").expect("peer sent a bad message")
+    }
+}
+
+/// Address of a localhost port nobody is listening on right now.
+fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+/// Server and client on an ephemeral port, connected to each other.
+fn connect(start_fen: Option<&str>) -> (Side, Side) {
+    let addr = free_addr();
+
+    let (server_tx, rx) = mpsc::channel();
+    let (tx_to_gui, server_rx) = mpsc::channel();
+    let bind = addr.clone();
+    let server = thread::spawn(move || start_server_with_channel(&bind, rx, tx_to_gui));
+
+    // the server may not be listening yet, a refused connection ends the client at once
+    for _ in 0..100 {
+        let (client_tx, rx) = mpsc::channel();
+        let (tx_to_gui, client_rx) = mpsc::channel();
+        let connect = addr.clone();
+        let client = thread::spawn(move || start_client_with_channel(&connect, rx, tx_to_gui));
+        thread::sleep(Duration::from_millis(20));
+        if !client.is_finished() {
+            let server = Side::new(start_fen, (server_tx, server_rx, server));
+            return (server, Side::new(start_fen, (client_tx, client_rx, client)));
+        }
+    }
+    panic!("could not connect to {}", addr);
+}
+
+/// Play `sans` in turn, White on the client and Black on the server. Each move is made on
+/// the mover's board, sent, and applied to the other board, which must then match its FEN.
+fn play(server: &mut Side, client: &mut Side, sans: &[&str]) {
+    for san in sans {
+        let (mover, other) = match client.board.move_turn {
+            Color::White => (&mut *client, &mut *server),
+            Color::Black => (&mut *server, &mut *client),
+        };
+        let (from, to, promo) = san_to_move(&mover.board, san).unwrap_or_else(|e| panic!("{}: {}", san, e));
+        make_move(&mut mover.board, &mut mover.info, from, to, promo).unwrap();
+        let status = status::evaluate(&mover.board, &mover.info, &[]);
+        let sent = board_move_to_message(from, to, promo, &mover.board, &mover.info, &status);
+        let frame = sent.serialize();
+        mover.tx.send(ProtocolMsg::Move(sent)).unwrap();
+
+        let ProtocolMsg::Move(received) = other.recv() else { panic!("expected the move {}", san) };
+        assert_eq!(received.serialize(), frame);
+        apply_message_to_board(&mut other.board, &mut other.info, &received).unwrap();
+        verify_fen(&other.board, &other.info, &received.fen).unwrap_or_else(|e| panic!("after {}: {}", san, e));
+    }
+}
+
+/// One side sends ChessQUIT and lets go of its channel. The other must get the message,
+/// then see the connection close, and both network threads must end without an error.
+fn quit(leaving: Side, staying: Side) {
+    leaving.tx.send(ProtocolMsg::Quit(QuitMsg { reason: "User exited".to_string() })).unwrap();
+    drop(leaving.tx);
+    let ProtocolMsg::Quit(quit) = staying.recv() else { panic!("expected ChessQUIT") };
+    assert_eq!(quit.reason, "User exited");
+    assert!(matches!(staying.rx.recv_timeout(WAIT), Err(RecvTimeoutError::Disconnected)));
+    leaving.thread.join().unwrap().unwrap();
+
+    drop(staying.tx);
+    staying.thread.join().unwrap().unwrap();
+}
+
+fn fen(side: &Side) -> String {
+    board_to_fen(&side.board, &side.info)
+}
+
+#[test]
+fn castling_and_en_passant() {
+    let (mut server, mut client) = connect(None);
+    play(
+        &mut server,
+        &mut client,
+        &["e4", "d5", "e5", "f5", "exf6", "Nxf6", "Nf3", "e6", "Bc4", "Be7", "O-O", "O-O", "d4", "c5", "dxc5", "b5"],
+    );
+    // white takes en passant right back
+    play(&mut server, &mut client, &["cxb6"]);
+    assert_eq!(fen(&client), fen(&server));
+    assert_eq!(fen(&client), "rnbq1rk1/p3b1pp/1P2pn2/3p4/2B5/5N2/PPP2PPP/RNBQ1RK1 b - - 0 9");
+    quit(client, server);
+}
+
+#[test]
+fn promotion_both_ways() {
+    let start = "4k3/1P6/8/8/8/8/6p1/4K3 w - - 0 1";
+    let (mut server, mut client) = connect(Some(start));
+    play(&mut server, &mut client, &["b8=Q+", "Ke7", "Qb4+", "Kf7", "Kf2", "g1=N", "Kxg1"]);
+    assert_eq!(fen(&client), fen(&server));
+    assert_eq!(fen(&client), "8/5k2/8/8/1Q6/8/8/6K1 b - - 0 4");
+    quit(server, client);
+}
+
+#[test]
+fn quit_before_any_move() {
+    let (server, client) = connect(None);
+    assert_eq!(fen(&client), fen(&server));
+    quit(client, server);
+}
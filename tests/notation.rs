@@ -0,0 +1,102 @@
+use eahla_gui::helper::fen_to_board;
+use eahla_gui::notation::{move_to_san, numbered_pairs, san_to_move};
+use eahla_gui::protocol::{position_to_square, square_to_position};
+use hermanha_chess::{Board, PieceType};
+
+fn san(board: &Board, from: &str, to: &str, promo: Option<PieceType>) -> Option<String> {
+    move_to_san(board, square_to_position(from).unwrap(), square_to_position(to).unwrap(), promo)
+}
+
+fn san_of(fen: &str, from: &str, to: &str) -> String {
+    let (board, _) = fen_to_board(fen).unwrap();
+    san(&board, from, to, None).unwrap()
+}
+
+#[test]
+fn pawn_and_piece_moves() {
+    let board = Board::start_pos();
+    assert_eq!(san(&board, "E2", "E4", None).as_deref(), Some("e4"));
+    assert_eq!(san(&board, "G1", "F3", None).as_deref(), Some("Nf3"));
+    assert_eq!(san(&board, "E2", "E5", None), None);
+}
+
+#[test]
+fn captures_and_en_passant() {
+    assert_eq!(san_of("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1", "E4", "D5"), "exd5");
+    assert_eq!(san_of("4k3/8/8/3p4/8/8/8/3QK3 w - - 0 1", "D1", "D5"), "Qxd5");
+
+    let mut board = Board::start_pos();
+    for (from, to) in [("E2", "E4"), ("A7", "A6"), ("E4", "E5"), ("D7", "D5")] {
+        board
+            .move_piece(square_to_position(from).unwrap(), square_to_position(to).unwrap(), None)
+            .unwrap();
+    }
+    assert_eq!(san(&board, "E5", "D6", None).as_deref(), Some("exd6"));
+}
+
+#[test]
+fn disambiguation() {
+    // knights on b1 and f1 can both reach d2
+    assert_eq!(san_of("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1", "B1", "D2"), "Nbd2");
+    // rooks on a1 and a5 share the file
+    assert_eq!(san_of("4k3/8/8/R7/8/8/8/R3K3 w - - 0 1", "A1", "A3"), "R1a3");
+    // queens on a1, a3 and c1 all reach b2 (a3 shares the file, c1 the rank)
+    assert_eq!(san_of("4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1", "A1", "B2"), "Qa1b2");
+}
+
+#[test]
+fn castling_promotion_check_and_mate() {
+    assert_eq!(san_of("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "E1", "G1"), "O-O");
+    assert_eq!(san_of("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", "E1", "C1"), "O-O-O");
+
+    let (board, _) = fen_to_board("7k/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+    assert_eq!(san(&board, "B7", "B8", Some(PieceType::Queen)).as_deref(), Some("b8=Q+"));
+    assert_eq!(san(&board, "B7", "B8", Some(PieceType::Knight)).as_deref(), Some("b8=N"));
+
+    assert_eq!(san_of("6k1/5ppp/8/8/8/8/8/3R2K1 w - - 0 1", "D1", "D8"), "Rd8#");
+}
+
+#[test]
+fn move_numbers() {
+    let sans: Vec<String> = ["e4", "e5", "Nf3"].iter().map(|s| s.to_string()).collect();
+    assert_eq!(numbered_pairs(&sans, 1, false), ["1. e4 e5", "2. Nf3"]);
+    assert_eq!(numbered_pairs(&sans, 12, true), ["12... e4", "13. e5 Nf3"]);
+    assert!(numbered_pairs(&[], 1, false).is_empty());
+}
+
+/// What `san` resolves to on the position, as "E2E4" plus the promotion piece.
+fn resolve(fen: &str, san: &str) -> Result<String, String> {
+    let (board, _) = fen_to_board(fen).unwrap();
+    san_to_move(&board, san).map(|(from, to, promo)| {
+        format!("{}{}{}", position_to_square(from), position_to_square(to), promo.map_or("", |p| match p {
+            PieceType::Queen => "Q",
+            PieceType::Knight => "N",
+            _ => "?",
+        }))
+    })
+}
+
+#[test]
+fn san_back_to_moves() {
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    assert_eq!(resolve(START, "e4").unwrap(), "E2E4");
+    assert_eq!(resolve(START, "Nf3+").unwrap(), "G1F3");
+    assert_eq!(resolve(START, "Ngf3").unwrap(), "G1F3");
+    assert!(resolve(START, "e5").is_err());
+    assert!(resolve(START, "Zz9").is_err());
+
+    let knights = "4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1";
+    assert!(resolve(knights, "Nd2").unwrap_err().contains("ambiguous"));
+    assert_eq!(resolve(knights, "Nfd2").unwrap(), "F1D2");
+
+    let castles = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+    assert_eq!(resolve(castles, "O-O").unwrap(), "E1G1");
+    assert_eq!(resolve(castles, "0-0-0").unwrap(), "E1C1");
+
+    let promotion = "7k/1P6/8/8/8/8/8/4K3 w - - 0 1";
+    assert_eq!(resolve(promotion, "b8=Q+").unwrap(), "B7B8Q");
+    assert_eq!(resolve(promotion, "b8N").unwrap(), "B7B8N");
+    assert!(resolve(promotion, "b8").is_err());
+    assert!(resolve(promotion, "b8=K").is_err());
+    assert_eq!(resolve("4k3/8/8/3p4/4P3/8/8/4K3 w - - 0 1", "exd5").unwrap(), "E4D5");
+}
@@ -0,0 +1,132 @@
+use eahla_gui::helper::board_to_fen;
+use eahla_gui::pgn::{parse_pgn, replay_game, result_tag, today, PgnGame, START_FEN};
+
+fn game(moves: &[&str], result: &str) -> PgnGame {
+    PgnGame {
+        white: "alice".to_string(),
+        black: "Opponent (client)".to_string(),
+        date: "2024.05.01".to_string(),
+        result: result.to_string(),
+        mode: Some("server".to_string()),
+        termination: None,
+        start_fen: START_FEN.to_string(),
+        moves: moves.iter().map(|m| m.to_string()).collect(),
+    }
+}
+
+#[test]
+fn headers_and_movetext() {
+    let pgn = game(&["f3", "e5", "g4", "Qh4#"], "0-1").to_pgn();
+    assert_eq!(
+        pgn,
+        "[Event \"Casual game\"]\n\
+         [Site \"?\"]\n\
+         [Date \"2024.05.01\"]\n\
+         [Round \"-\"]\n\
+         [White \"alice\"]\n\
+         [Black \"Opponent (client)\"]\n\
+         [Result \"0-1\"]\n\
+         [Mode \"server\"]\n\
+         \n\
+         1. f3 e5 2. g4 Qh4# 0-1\n"
+    );
+}
+
+#[test]
+fn unfinished_game_from_a_position() {
+    let mut game = game(&["Kb1", "Kb8"], "0-0");
+    game.mode = None;
+    game.termination = Some("User said \"bye\"".to_string());
+    game.start_fen = "k7/8/8/8/8/8/8/K7 b - - 3 40".to_string();
+    let pgn = game.to_pgn();
+    assert!(pgn.contains("[Result \"*\"]\n"));
+    assert!(!pgn.contains("[Mode"));
+    assert!(pgn.contains("[Termination \"User said \\\"bye\\\"\"]\n"));
+    assert!(pgn.contains("[SetUp \"1\"]\n[FEN \"k7/8/8/8/8/8/8/K7 b - - 3 40\"]\n"));
+    assert!(pgn.ends_with("\n40... Kb1 41. Kb8 *\n"));
+}
+
+#[test]
+fn long_games_are_wrapped() {
+    let moves: Vec<&str> = ["Nf3", "Nf6", "Ng1", "Ng8"].iter().cycle().take(60).copied().collect();
+    let pgn = game(&moves, "1/2-1/2").to_pgn();
+    let movetext = pgn.split("\n\n").nth(1).unwrap();
+    assert!(movetext.lines().count() > 1);
+    assert!(movetext.lines().all(|line| line.len() <= 79));
+    assert!(movetext.starts_with("1. Nf3 Nf6 2. Ng1 Ng8"));
+    assert!(movetext.trim_end().ends_with("30. Ng1 Ng8 1/2-1/2"));
+}
+
+#[test]
+fn results_and_date() {
+    assert_eq!(result_tag("1-0"), "1-0");
+    assert_eq!(result_tag("1/2-1/2"), "1/2-1/2");
+    assert_eq!(result_tag("0-0"), "*");
+
+    let date = today();
+    assert_eq!(date.len(), 10);
+    assert_eq!(&date[4..5], ".");
+    assert!(date.as_str() >= "2024.01.01");
+}
+
+#[test]
+fn parse_skips_comments_nags_and_variations() {
+    let text = "[Event \"Club night\"]\n\
+                [White \"Anna \\\"the rook\\\"\"]\n\
+                [Black \"Bo\"]\n\
+                [Result \"1-0\"]\n\
+                \n\
+                1. e4 {best by test} e5 $1 2. Nf3 (2. f4 exf4 (2... d5)) 2... Nc6 ; a comment\n\
+                3.Bb5! a6?! 1-0\n\
+                \n\
+                [Event \"Next game\"]\n\
+                1. d4 *\n";
+    let game = parse_pgn(text).unwrap();
+    assert_eq!(game.white, "Anna \"the rook\"");
+    assert_eq!(game.black, "Bo");
+    assert_eq!(game.result, "1-0");
+    assert_eq!(game.moves, ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6"]);
+
+    let replayed = replay_game(&game).unwrap();
+    assert_eq!(replayed.positions.len(), 7);
+    let (board, info) = &replayed.positions[6];
+    assert_eq!(board_to_fen(board, info), "r1bqkbnr/1ppp1ppp/p1n5/1B2p3/4P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 0 4");
+
+    assert!(parse_pgn("1. e4 { never closed").is_err());
+    assert!(parse_pgn("1. e4 (1. d4").is_err());
+}
+
+#[test]
+fn saved_games_read_back() {
+    let saved = game(&["e4", "d5", "exd5", "Qxd5", "Nc3", "Qa5"], "0-0");
+    let read = parse_pgn(&saved.to_pgn()).unwrap();
+    assert_eq!(read.moves, saved.moves);
+    assert_eq!(read.result, "0-0");
+    assert_eq!(read.mode.as_deref(), Some("server"));
+    assert_eq!(replay_game(&read).unwrap().moves[3].san, "Qxd5");
+
+    let mut from_position = game(&["Kb2", "Ka7"], "0-0");
+    from_position.start_fen = "k7/8/8/8/8/8/8/K7 w - - 0 1".to_string();
+    let read = parse_pgn(&from_position.to_pgn()).unwrap();
+    assert_eq!(read.start_fen, "k7/8/8/8/8/8/8/K7 w - - 0 1");
+    assert_eq!(replay_game(&read).unwrap().positions.len(), 3);
+
+    let Err(e) = replay_game(&game(&["e4", "e4"], "0-0")) else { panic!("e4 twice replayed") };
+    assert!(e.starts_with("move 2 (e4)"));
+}
+
+#[test]
+fn saving_never_overwrites() {
+    let dir = std::env::temp_dir().join(format!("eahla-pgn-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let first = game(&["e4"], "0-0").save_new(&dir, Some("server")).unwrap();
+    let second = game(&["d4"], "0-0").save_new(&dir, Some("server")).unwrap();
+    let first_text = std::fs::read_to_string(&first).unwrap();
+    let second_text = std::fs::read_to_string(&second).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_ne!(first, second);
+    assert!(first.file_name().unwrap().to_str().unwrap().starts_with("game-"));
+    assert_eq!(parse_pgn(&first_text).unwrap().moves, ["e4"]);
+    assert_eq!(parse_pgn(&second_text).unwrap().moves, ["d4"]);
+}
@@ -0,0 +1,216 @@
+use eahla_gui::clock::TimeControl;
+use eahla_gui::protocol::{
+    server_color, ClockMsg, ColorChoice, GameAction, GameActionMsg, HelloMsg, MoveMsg, ProtocolError, ProtocolMsg, QuitMsg,
+    TakebackAction, TakebackMsg,
+};
+use hermanha_chess::Color;
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR";
+
+fn move_frame(move_str: &str, game_state: &str, fen: &str) -> String {
+    MoveMsg {
+        move_str: move_str.to_string(),
+        game_state: game_state.to_string(),
+        fen: fen.to_string(),
+    }
+    .serialize()
+}
+
+#[test]
+fn move_round_trip() {
+    let raw = move_frame("E2E40", "0-0", START_FEN);
+    match ProtocolMsg::deserialize(&raw) {
+        Ok(ProtocolMsg::Move(m)) => {
+            assert_eq!(m.move_str, "E2E40");
+            assert_eq!(m.game_state, "0-0");
+            assert_eq!(m.fen, START_FEN);
+        }
+        _ => panic!("expected a move"),
+    }
+}
+
+#[test]
+fn quit_round_trip() {
+    let raw = QuitMsg { reason: "user quit".to_string() }.serialize();
+    match ProtocolMsg::deserialize(&raw) {
+        Ok(ProtocolMsg::Quit(q)) => assert_eq!(q.reason, "user quit"),
+        _ => panic!("expected a quit"),
+    }
+}
+
+#[test]
+fn quit_reasons_reach_the_peer() {
+    let errors = [
+        ProtocolError::UnknownHeader("ChessMOOV".to_string()),
+        ProtocolError::WrongLength(12),
+        ProtocolError::NonAscii,
+        ProtocolError::MissingField("fen"),
+        ProtocolError::BadMove("E9E9".to_string()),
+        ProtocolError::BadGameState("2-2".to_string()),
+        ProtocolError::BadFen("x".to_string()),
+        ProtocolError::BadPadding("x".to_string()),
+        ProtocolError::BadColor("green".to_string()),
+        ProtocolError::BadTakeback("x".to_string()),
+        ProtocolError::BadGameAction("x".to_string()),
+        ProtocolError::BadTime("x".to_string()),
+    ];
+    for error in errors {
+        let reason = error.quit_reason();
+        let raw = QuitMsg { reason: reason.to_string() }.serialize();
+        match ProtocolMsg::deserialize(&raw) {
+            Ok(ProtocolMsg::Quit(q)) => assert_eq!(q.reason, reason),
+            other => panic!("{}: {:?}", reason, other.err()),
+        }
+    }
+
+    let mut raw = QuitMsg { reason: "desync: bad fen".to_string() }.serialize();
+    raw.replace_range(127.., "x");
+    assert!(matches!(ProtocolMsg::deserialize(&raw), Err(ProtocolError::BadPadding(_))));
+}
+
+#[test]
+fn unknown_header() {
+    let raw = move_frame("E2E40", "0-0", START_FEN).replacen("ChessMOVE", "ChessMOOV", 1);
+    assert_eq!(
+        ProtocolMsg::deserialize(&raw).err(),
+        Some(ProtocolError::UnknownHeader("ChessMOOV".to_string()))
+    );
+}
+
+#[test]
+fn truncated_frame() {
+    let raw = move_frame("E2E40", "0-0", START_FEN);
+    assert_eq!(
+        ProtocolMsg::deserialize(&raw[..100]).err(),
+        Some(ProtocolError::WrongLength(100))
+    );
+}
+
+#[test]
+fn bad_fields() {
+    let bad_move = move_frame("E2X40", "0-0", START_FEN);
+    assert!(matches!(MoveMsg::deserialize(&bad_move), Err(ProtocolError::BadMove(_))));
+
+    let bad_promo = move_frame("E7E8X", "0-0", START_FEN);
+    assert!(matches!(MoveMsg::deserialize(&bad_promo), Err(ProtocolError::BadMove(_))));
+
+    let bad_state = move_frame("E2E40", "2-0", START_FEN);
+    assert!(matches!(MoveMsg::deserialize(&bad_state), Err(ProtocolError::BadGameState(_))));
+
+    let bad_fen = move_frame("E2E40", "0-0", "rnbqkbnr/pppppppp/8/8/8/PPPPPPPP/RNBQKBNR");
+    assert!(matches!(MoveMsg::deserialize(&bad_fen), Err(ProtocolError::BadFen(_))));
+}
+
+#[test]
+fn bad_padding() {
+    let mut raw = move_frame("E2E40", "0-0", START_FEN);
+    raw.replace_range(127.., "x");
+    assert!(matches!(ProtocolMsg::deserialize(&raw), Err(ProtocolError::BadPadding(_))));
+
+    let mut raw = QuitMsg { reason: "bye".to_string() }.serialize();
+    raw.replace_range(127.., "é");
+    assert_eq!(ProtocolMsg::deserialize(&raw).err(), Some(ProtocolError::NonAscii));
+}
+
+#[test]
+fn hello_round_trip() {
+    let raw = HelloMsg { color: ColorChoice::Random, time: None }.serialize();
+    assert_eq!(raw.len(), 128);
+    match ProtocolMsg::deserialize(&raw) {
+        Ok(ProtocolMsg::Hello(h)) => {
+            assert_eq!(h.color, ColorChoice::Random);
+            assert_eq!(h.time, None);
+        }
+        _ => panic!("expected a hello"),
+    }
+
+    let timed = HelloMsg { color: ColorChoice::White, time: Some(TimeControl::parse("5+3").unwrap()) }.serialize();
+    assert!(timed.starts_with("ChessHELO:white:5+3:0"));
+    match ProtocolMsg::deserialize(&timed) {
+        Ok(ProtocolMsg::Hello(h)) => assert_eq!(h.time, Some(TimeControl::parse("5+3").unwrap())),
+        _ => panic!("expected a hello"),
+    }
+    assert_eq!(
+        ProtocolMsg::deserialize(&timed.replacen("5+3", "5+x", 1)).err(),
+        Some(ProtocolError::BadTime("5+x".to_string()))
+    );
+
+    let bad = raw.replacen("random", "purple", 1);
+    assert_eq!(
+        ProtocolMsg::deserialize(&bad).err(),
+        Some(ProtocolError::BadColor("purple".to_string()))
+    );
+}
+
+#[test]
+fn colour_negotiation() {
+    // a server that asked for a colour gets it
+    assert_eq!(server_color(Some(ColorChoice::White), ColorChoice::White), Color::White);
+    assert_eq!(server_color(Some(ColorChoice::Black), ColorChoice::Random), Color::Black);
+    // otherwise the client decides
+    assert_eq!(server_color(None, ColorChoice::White), Color::Black);
+    assert_eq!(server_color(None, ColorChoice::Black), Color::White);
+    assert_eq!(server_color(Some(ColorChoice::Random), ColorChoice::Black), Color::White);
+}
+
+#[test]
+fn takeback_round_trip() {
+    let request = TakebackMsg { action: TakebackAction::Request, plies: 2, moves: 14 };
+    let raw = request.serialize();
+    assert_eq!(raw.len(), 128);
+    assert!(raw.starts_with("ChessTAKE:request:2:14:0"));
+    match ProtocolMsg::deserialize(&raw) {
+        Ok(ProtocolMsg::Takeback(t)) => assert_eq!(t, request),
+        _ => panic!("expected a takeback"),
+    }
+
+    let accept = TakebackMsg { action: TakebackAction::Accept, ..request }.serialize();
+    assert!(matches!(
+        ProtocolMsg::deserialize(&accept),
+        Ok(ProtocolMsg::Takeback(TakebackMsg { action: TakebackAction::Accept, plies: 2, moves: 14 }))
+    ));
+
+    for bad in [raw.replacen("request", "requezt", 1), raw.replacen(":2:", ":3:", 1), raw.replacen(":14:", ":1x:", 1)] {
+        assert!(matches!(ProtocolMsg::deserialize(&bad), Err(ProtocolError::BadTakeback(_))), "{}", bad);
+    }
+    // more moves to take back than were played
+    let early = TakebackMsg { action: TakebackAction::Request, plies: 2, moves: 1 }.serialize();
+    assert!(matches!(ProtocolMsg::deserialize(&early), Err(ProtocolError::BadTakeback(_))));
+}
+
+#[test]
+fn game_action_round_trip() {
+    for action in [
+        GameAction::Resign,
+        GameAction::OfferDraw,
+        GameAction::AcceptDraw,
+        GameAction::DeclineDraw,
+        GameAction::Abort,
+    ] {
+        let raw = GameActionMsg { action }.serialize();
+        assert_eq!(raw.len(), 128);
+        match ProtocolMsg::deserialize(&raw) {
+            Ok(ProtocolMsg::Game(g)) => assert_eq!(g.action, action),
+            _ => panic!("expected a game action"),
+        }
+    }
+
+    let raw = GameActionMsg { action: GameAction::Resign }.serialize().replacen("resign", "resing", 1);
+    assert_eq!(
+        ProtocolMsg::deserialize(&raw).err(),
+        Some(ProtocolError::BadGameAction("resing".to_string()))
+    );
+}
+
+#[test]
+fn clock_round_trip() {
+    let msg = ClockMsg { white_ms: 297_000, black_ms: 0 };
+    let raw = msg.serialize();
+    assert!(raw.starts_with("ChessTIME:297000:0:0"));
+    match ProtocolMsg::deserialize(&raw) {
+        Ok(ProtocolMsg::Clock(c)) => assert_eq!(c, msg),
+        _ => panic!("expected clock times"),
+    }
+    let bad = raw.replacen("297000", "-29700", 1);
+    assert_eq!(ProtocolMsg::deserialize(&bad).err(), Some(ProtocolError::BadTime("-29700".to_string())));
+}
@@ -0,0 +1,60 @@
+use eahla_gui::helper::fen_to_board;
+use eahla_gui::protocol::{position_to_square, square_to_position};
+use eahla_gui::rules::{is_in_check, is_promotion, legal_moves, legal_targets};
+use hermanha_chess::{Board, Color};
+
+/// Legal targets of the piece on `from` as sorted square names.
+fn targets(board: &Board, from: &str) -> Vec<String> {
+    let mut squares: Vec<String> = legal_targets(board, square_to_position(from).unwrap())
+        .into_iter()
+        .map(position_to_square)
+        .collect();
+    squares.sort();
+    squares
+}
+
+#[test]
+fn start_position_has_twenty_moves() {
+    let board = Board::start_pos();
+    assert_eq!(legal_moves(&board).len(), 20);
+    assert_eq!(targets(&board, "G1"), ["F3", "H3"]);
+    assert_eq!(targets(&board, "E2"), ["E3", "E4"]);
+    // not black's turn
+    assert!(targets(&board, "E7").is_empty());
+}
+
+#[test]
+fn castling_and_captures() {
+    let (board, _) = fen_to_board("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+    assert_eq!(targets(&board, "E1"), ["C1", "D1", "D2", "E2", "F1", "F2", "G1"]);
+    assert!(targets(&board, "A1").contains(&"A8".to_string()));
+}
+
+#[test]
+fn en_passant() {
+    let mut board = Board::start_pos();
+    for (from, to) in [("E2", "E4"), ("A7", "A6"), ("E4", "E5"), ("D7", "D5")] {
+        board
+            .move_piece(square_to_position(from).unwrap(), square_to_position(to).unwrap(), None)
+            .unwrap();
+    }
+    assert_eq!(targets(&board, "E5"), ["D6", "E6"]);
+}
+
+#[test]
+fn pinned_piece_can_not_leave_the_line() {
+    let (board, _) = fen_to_board("4r1k1/8/8/8/8/8/4B3/4K3 w - - 0 1").unwrap();
+    assert!(targets(&board, "E2").is_empty());
+}
+
+#[test]
+fn check_and_promotion() {
+    let (board, _) = fen_to_board("4k3/1P6/8/8/8/8/8/4K2r w - - 0 1").unwrap();
+    assert!(is_in_check(&board, Color::White));
+    assert!(!is_in_check(&board, Color::Black));
+
+    let (board, _) = fen_to_board("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+    let b7 = square_to_position("B7").unwrap();
+    assert!(is_promotion(&board, b7, square_to_position("B8").unwrap()));
+    assert_eq!(targets(&board, "B7"), ["B8"]);
+}
@@ -0,0 +1,364 @@
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use eahla_gui::cli::{parse_args, Options};
+use eahla_gui::helper::{board_move_to_message, board_to_fen, make_move, PositionInfo};
+use eahla_gui::network::Channels;
+use eahla_gui::pgn::{replay_game, PgnGame, START_FEN};
+use eahla_gui::protocol::{square_to_position, ColorChoice, HelloMsg, ProtocolMsg};
+use eahla_gui::session::GameSession;
+use eahla_gui::status::GameStatus;
+use hermanha_chess::{Board, Color, Position};
+
+fn session(args: &[&str]) -> GameSession {
+    let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+    let options: Options = parse_args(&args).unwrap();
+    GameSession::new(&options).unwrap()
+}
+
+/// Two channel pairs wired into each other, standing in for the network threads.
+fn linked() -> (Channels, Channels) {
+    let (to_server, from_client) = mpsc::channel();
+    let (to_client, from_server) = mpsc::channel();
+    let (client_tx, client_rx) = mpsc::channel();
+    let (server_tx, server_rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        for msg in from_client {
+            let _ = server_tx.send(Ok(msg));
+        }
+    });
+    std::thread::spawn(move || {
+        for msg in from_server {
+            let _ = client_tx.send(Ok(msg));
+        }
+    });
+    ((to_server, client_rx), (to_client, server_rx))
+}
+
+/// Poll both sides until `done` holds, the relay threads need a moment.
+fn settle(client: &mut GameSession, server: &mut GameSession, done: impl Fn(&GameSession, &GameSession) -> bool) {
+    for _ in 0..200 {
+        client.poll();
+        server.poll();
+        if done(client, server) {
+            return;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    panic!("sessions did not settle");
+}
+
+#[test]
+fn local_moves_and_undo() {
+    let mut game = session(&[]);
+    assert!(game.can_move() && game.can_undo());
+    assert!(game.play_move(Position::new(1, 4), Position::new(3, 4), None).is_ok());
+    assert!(game.play_move(Position::new(1, 4), Position::new(3, 4), None).is_err()); // nothing on e2 now
+    assert_eq!(game.move_lines(), vec!["1. e4".to_string()]);
+    assert_eq!(game.last_move(), Some((Position::new(1, 4), Position::new(3, 4))));
+
+    game.undo();
+    assert!(game.moves().is_empty());
+    assert_eq!(game.board().move_turn, Color::White);
+    game.redo();
+    assert_eq!(game.moves().len(), 1);
+}
+
+#[test]
+fn handshake_and_a_move_over_channels() {
+    let mut client = session(&["client", "--color", "black"]);
+    let mut server = session(&["server"]);
+    let (client_channels, server_channels) = linked();
+    client.connect(client_channels);
+    server.connect(server_channels);
+    assert!(client.negotiating() && !client.can_move());
+
+    settle(&mut client, &mut server, |c, s| !c.negotiating() && !s.negotiating());
+    assert_eq!(client.my_color(), Color::Black);
+    assert_eq!(server.my_color(), Color::White);
+    assert!(!client.can_move() && server.can_move());
+    assert!(!client.can_undo());
+
+    server.play_move(Position::new(1, 3), Position::new(3, 3), None).unwrap();
+    settle(&mut client, &mut server, |c, _| c.moves().len() == 1);
+    assert_eq!(client.moves()[0].san, "d4");
+    assert!(client.can_move() && !server.can_move());
+    assert!(client.desync().is_none() && server.desync().is_none());
+}
+
+/// Client and server sessions that agreed on colours, the client playing White.
+fn connected() -> (GameSession, GameSession) {
+    let mut client = session(&["client"]);
+    let mut server = session(&["server"]);
+    let (client_channels, server_channels) = linked();
+    client.connect(client_channels);
+    server.connect(server_channels);
+    settle(&mut client, &mut server, |c, s| !c.negotiating() && !s.negotiating());
+    (client, server)
+}
+
+#[test]
+fn opponent_quitting_ends_the_game() {
+    let (mut client, mut server) = connected();
+    client.play_move(Position::new(1, 4), Position::new(3, 4), None).unwrap();
+    client.quit();
+    settle(&mut client, &mut server, |_, s| s.game_over().is_some());
+    let over = server.game_over().unwrap();
+    assert_eq!(over.reason, "Opponent left: User exited");
+    assert_eq!(over.result, "*");
+    assert!(!server.can_move());
+    assert!(server.play_move(Position::new(6, 4), Position::new(4, 4), None).is_err());
+}
+
+#[test]
+fn lost_connection_ends_the_game() {
+    let (client, mut server) = connected();
+    drop(client); // no ChessQUIT, the channel just closes
+    for _ in 0..200 {
+        if server.poll() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    let over = server.game_over().expect("the game should be over");
+    assert_eq!(over.reason, "Opponent left: connection closed");
+    assert!(!server.can_move());
+}
+
+/// Play `coords` like "e2e4" for whoever is to move in `game`.
+fn play(game: &mut GameSession, coords: &str) {
+    let from = square_to_position(&coords[0..2]).unwrap();
+    let to = square_to_position(&coords[2..4]).unwrap();
+    game.play_move(from, to, None).unwrap_or_else(|e| panic!("{}: {}", coords, e));
+}
+
+fn fen(game: &GameSession) -> String {
+    board_to_fen(game.board(), game.position_info())
+}
+
+#[test]
+fn takeback_accepted_rolls_back_both_boards() {
+    let (mut client, mut server) = connected();
+    play(&mut client, "e2e4");
+    settle(&mut client, &mut server, |_, s| s.moves().len() == 1);
+    play(&mut server, "e7e5");
+    settle(&mut client, &mut server, |c, _| c.moves().len() == 2);
+
+    // our move and the answer to it
+    client.request_takeback();
+    assert!(!client.can_move());
+    settle(&mut client, &mut server, |_, s| s.takeback_prompt().is_some());
+    assert_eq!(server.takeback_prompt().unwrap().plies, 2);
+    assert!(!server.can_move());
+    server.answer_takeback(true);
+    settle(&mut client, &mut server, |c, _| c.takeback_sent().is_none());
+
+    assert!(client.moves().is_empty() && server.moves().is_empty());
+    assert_eq!(fen(&client), START_FEN);
+    assert_eq!(fen(&server), START_FEN);
+    assert!(client.can_move() && !server.can_move());
+    assert!(client.desync().is_none() && server.desync().is_none());
+}
+
+#[test]
+fn takeback_declined_keeps_the_moves() {
+    let (mut client, mut server) = connected();
+    play(&mut client, "e2e4");
+    client.request_takeback();
+    settle(&mut client, &mut server, |_, s| s.takeback_prompt().is_some());
+    assert_eq!(server.takeback_prompt().unwrap().plies, 1);
+    server.answer_takeback(false);
+    settle(&mut client, &mut server, |c, _| c.takeback_sent().is_none());
+
+    assert_eq!(client.moves().len(), 1);
+    assert_eq!(fen(&client), fen(&server));
+    assert!(server.can_move() && !client.can_move());
+}
+
+#[test]
+fn draw_offer_accepted() {
+    let (mut client, mut server) = connected();
+    play(&mut client, "d2d4");
+    client.offer_draw();
+    assert!(client.draw_offered());
+    settle(&mut client, &mut server, |_, s| s.draw_prompt());
+    assert!(!server.can_move());
+    server.answer_draw(true);
+    settle(&mut client, &mut server, |c, _| c.game_over().is_some());
+
+    for game in [&client, &server] {
+        let over = game.game_over().unwrap();
+        assert_eq!((over.result.as_str(), over.reason.as_str()), ("1/2-1/2", "Draw agreed"));
+    }
+}
+
+#[test]
+fn draw_offer_declined() {
+    let (mut client, mut server) = connected();
+    client.offer_draw();
+    settle(&mut client, &mut server, |_, s| s.draw_prompt());
+    server.answer_draw(false);
+    settle(&mut client, &mut server, |c, _| !c.draw_offered());
+    assert!(client.game_over().is_none() && server.game_over().is_none());
+    assert!(client.can_move());
+}
+
+#[test]
+fn abort_before_both_moved_and_resign_after() {
+    let (mut client, mut server) = connected();
+    play(&mut client, "e2e4");
+    assert!(client.can_abort());
+    client.resign();
+    settle(&mut client, &mut server, |_, s| s.game_over().is_some());
+    assert_eq!(client.game_over().unwrap().reason, "Game aborted by White");
+    assert_eq!(server.game_over().unwrap().reason, "Game aborted by White");
+    assert_eq!(server.game_over().unwrap().result, "*");
+
+    let (mut client, mut server) = connected();
+    play(&mut client, "e2e4");
+    settle(&mut client, &mut server, |_, s| s.moves().len() == 1);
+    play(&mut server, "e7e5");
+    assert!(!server.can_abort());
+    server.resign();
+    settle(&mut client, &mut server, |c, _| c.game_over().is_some());
+    assert_eq!(client.game_over().unwrap().result, "1-0");
+    assert_eq!(server.game_over().unwrap().result, "1-0");
+    assert!(!client.can_move());
+}
+
+#[test]
+fn clocks_follow_the_opponent() {
+    let mut client = session(&["client", "--time", "5+0"]);
+    let mut server = session(&["server"]);
+    let (client_channels, server_channels) = linked();
+    client.connect(client_channels);
+    server.connect(server_channels);
+    settle(&mut client, &mut server, |c, s| !c.negotiating() && !s.negotiating());
+    assert!(client.clock().is_some() && server.clock().is_some()); // the server takes the client's --time
+
+    play(&mut client, "e2e4");
+    // the ChessTIME right behind the move sets the server's clocks to the client's
+    let synced = |c: &GameSession, s: &GameSession| {
+        let now = Instant::now();
+        let (theirs, ours) = (c.clock().unwrap(), s.clock().unwrap());
+        let white = theirs.remaining(Color::White, now).abs_diff(ours.remaining(Color::White, now));
+        s.moves().len() == 1 && ours.running() == Some(Color::Black) && white < Duration::from_millis(500)
+    };
+    settle(&mut client, &mut server, synced);
+    assert_eq!(client.clock().unwrap().running(), Some(Color::Black));
+}
+
+#[test]
+fn different_boards_end_in_desync() {
+    // the client thinks the game started from a bare endgame, the server from the start position
+    let mut client = session(&["client", "--fen", "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"]);
+    let mut server = session(&["server"]);
+    let (client_channels, server_channels) = linked();
+    client.connect(client_channels);
+    server.connect(server_channels);
+    settle(&mut client, &mut server, |c, s| !c.negotiating() && !s.negotiating());
+
+    play(&mut client, "e2e4");
+    settle(&mut client, &mut server, |c, s| s.desync().is_some() && c.game_over().is_some());
+    assert!(server.desync().unwrap().starts_with("board differs after E2E4"));
+    assert!(!server.can_move());
+    // the server tells the client why it stopped
+    assert_eq!(client.game_over().unwrap().reason, "Opponent left: desync");
+    assert!(!client.can_move());
+}
+
+#[test]
+fn finished_games_are_saved_in_pgn_dir() {
+    let dir = std::env::temp_dir().join(format!("eahla-session-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let mut game = session(&["--pgn-dir", dir.to_str().unwrap()]);
+    for coords in ["f2f3", "e7e5", "g2g4", "d8h4"] {
+        play(&mut game, coords);
+    }
+    let saved: Vec<_> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+    let text = std::fs::read_to_string(&saved[0]).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(game.game_over().unwrap().result, "0-1");
+    assert_eq!(saved.len(), 1);
+    assert!(text.contains("[Result \"0-1\"]"));
+}
+
+#[test]
+fn replay_steps_through_a_saved_game() {
+    let saved = PgnGame {
+        white: "alice".to_string(),
+        black: "bob".to_string(),
+        date: "2024.05.01".to_string(),
+        result: "*".to_string(),
+        mode: None,
+        termination: None,
+        start_fen: START_FEN.to_string(),
+        moves: ["e4", "e5", "Nf3"].iter().map(|m| m.to_string()).collect(),
+    };
+    let mut game = session(&[]);
+    game.load_replay(replay_game(&saved).unwrap());
+    assert_eq!(game.replay_position(), Some(0));
+    assert_eq!(fen(&game), START_FEN);
+    assert_eq!(game.last_move(), None);
+    assert!(!game.can_move() && !game.can_undo());
+
+    game.show_position(2);
+    assert_eq!(game.last_move(), Some((Position::new(6, 4), Position::new(4, 4))));
+    assert_eq!(game.board().move_turn, Color::White);
+    assert_eq!(game.moves().len(), 3);
+    game.show_position(usize::MAX);
+    assert_eq!(game.replay_position(), Some(3));
+    assert_eq!(game.board().move_turn, Color::Black);
+
+    game.new_game();
+    assert_eq!(game.replay_position(), None);
+    assert!(game.can_move());
+}
+
+#[test]
+fn a_result_we_do_not_see_is_a_desync() {
+    let mut server = session(&["server"]);
+    let (to_peer, from_server) = mpsc::channel();
+    let (to_server, from_peer) = mpsc::channel();
+    server.connect((to_peer, from_peer));
+    to_server.send(Ok(ProtocolMsg::Hello(HelloMsg { color: ColorChoice::White, time: None }))).unwrap();
+    server.poll();
+    assert!(!server.negotiating());
+
+    // 1. e4, claimed to have won the game
+    let (from, to) = (Position::new(1, 4), Position::new(3, 4));
+    let (mut board, mut info) = (Board::start_pos(), PositionInfo::start_pos());
+    make_move(&mut board, &mut info, from, to, None).unwrap();
+    let mut claim = board_move_to_message(from, to, None, &board, &info, &GameStatus::Ongoing);
+    claim.game_state = "1-0".to_string();
+    to_server.send(Ok(ProtocolMsg::Move(claim))).unwrap();
+    server.poll();
+
+    let why = server.desync().unwrap();
+    assert!(why.starts_with("opponent says 1-0 after E2E4") && why.ends_with("we see 0-0"), "{}", why);
+    assert!(server.game_over().is_none() && !server.can_move());
+    let Some(ProtocolMsg::Quit(quit)) = from_server.try_iter().last() else { panic!("expected ChessQUIT") };
+    assert_eq!(quit.reason, "desync");
+}
+
+#[test]
+fn a_peer_without_the_handshake_gets_the_usual_colours() {
+    let mut server = session(&["server", "--color", "white", "--time", "5+0"]);
+    let (to_peer, _from_server) = mpsc::channel();
+    let (to_server, from_peer) = mpsc::channel();
+    server.connect((to_peer, from_peer));
+
+    // a plain chesstp client playing White opens with 1. e4
+    let (from, to) = (Position::new(1, 4), Position::new(3, 4));
+    let (mut board, mut info) = (Board::start_pos(), PositionInfo::start_pos());
+    make_move(&mut board, &mut info, from, to, None).unwrap();
+    to_server.send(Ok(ProtocolMsg::Move(board_move_to_message(from, to, None, &board, &info, &GameStatus::Ongoing)))).unwrap();
+    server.poll();
+
+    assert!(server.desync().is_none() && !server.negotiating());
+    assert_eq!(server.my_color(), Color::Black);
+    assert_eq!(server.moves().len(), 1);
+    assert!(server.clock().is_none());
+    assert!(server.can_move());
+}
@@ -0,0 +1,128 @@
+use eahla_gui::helper::{board_move_to_message, fen_to_board, make_move, position_key, PositionInfo};
+use eahla_gui::protocol::square_to_position;
+use eahla_gui::status::{evaluate, GameOver, GameStatus};
+use hermanha_chess::{Board, Color};
+
+fn status_of(fen: &str) -> GameStatus {
+    let (board, info) = fen_to_board(fen).unwrap();
+    evaluate(&board, &info, &[position_key(&board, &info)])
+}
+
+/// Plays the moves and returns the status after each one, tracking history like the GUI does.
+fn play(board: &mut Board, info: &mut PositionInfo, moves: &[&str]) -> Vec<GameStatus> {
+    let mut history = vec![position_key(board, info)];
+    let mut statuses = Vec::new();
+    for mv in moves {
+        let from = square_to_position(&mv[0..2]).unwrap();
+        let to = square_to_position(&mv[2..4]).unwrap();
+        make_move(board, info, from, to, None).unwrap();
+        history.push(position_key(board, info));
+        statuses.push(evaluate(board, info, &history));
+    }
+    statuses
+}
+
+#[test]
+fn start_position_is_ongoing() {
+    let (board, info) = (Board::start_pos(), PositionInfo::start_pos());
+    assert_eq!(evaluate(&board, &info, &[position_key(&board, &info)]), GameStatus::Ongoing);
+}
+
+#[test]
+fn fools_mate() {
+    let mut board = Board::start_pos();
+    let mut info = PositionInfo::start_pos();
+    let statuses = play(&mut board, &mut info, &["F2F3", "E7E5", "G2G4", "D8H4"]);
+
+    let status = statuses[3];
+    assert_eq!(status, GameStatus::Checkmate { winner: Color::Black });
+    let msg = board_move_to_message(
+        square_to_position("D8").unwrap(),
+        square_to_position("H4").unwrap(),
+        None,
+        &board,
+        &info,
+        &status,
+    );
+    assert_eq!(msg.game_state, "0-1");
+}
+
+#[test]
+fn back_rank_mate_for_white() {
+    assert_eq!(status_of("3R2k1/5ppp/8/8/8/8/8/6K1 b - - 1 1"), GameStatus::Checkmate { winner: Color::White });
+}
+
+#[test]
+fn stalemate() {
+    let status = status_of("7k/5Q2/6K1/8/8/8/8/8 b - - 0 1");
+    assert_eq!(status, GameStatus::Stalemate);
+    assert_eq!(status.game_state_str(), "1/2-1/2");
+}
+
+#[test]
+fn fifty_move_rule() {
+    assert_eq!(status_of("4k3/8/8/8/8/8/8/R3K3 w - - 100 80"), GameStatus::FiftyMoveRule);
+    assert_eq!(status_of("4k3/8/8/8/8/8/8/R3K3 w - - 99 80"), GameStatus::Ongoing);
+}
+
+#[test]
+fn insufficient_material() {
+    assert_eq!(status_of("4k3/8/8/8/8/8/8/4K3 w - - 0 1"), GameStatus::InsufficientMaterial);
+    assert_eq!(status_of("4k3/8/8/8/8/8/8/2N1K3 w - - 0 1"), GameStatus::InsufficientMaterial);
+    // both bishops on dark squares
+    assert_eq!(status_of("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1"), GameStatus::InsufficientMaterial);
+    assert_eq!(status_of("4k1b1/8/8/8/8/8/8/2B1K3 w - - 0 1"), GameStatus::Ongoing);
+    assert_eq!(status_of("4k3/8/8/8/8/8/8/1NN1K3 w - - 0 1"), GameStatus::Ongoing);
+    assert_eq!(status_of("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1"), GameStatus::Ongoing);
+}
+
+#[test]
+fn threefold_repetition() {
+    let mut board = Board::start_pos();
+    let mut info = PositionInfo::start_pos();
+    let shuffle = ["G1F3", "G8F6", "F3G1", "F6G8"];
+    let moves: Vec<&str> = shuffle.iter().chain(shuffle.iter()).copied().collect();
+
+    let statuses = play(&mut board, &mut info, &moves);
+
+    // the start position comes back after move 4 and again after move 8
+    assert!(statuses[..7].iter().all(|s| *s == GameStatus::Ongoing));
+    assert_eq!(statuses[7], GameStatus::ThreefoldRepetition);
+    assert_eq!(statuses[7].game_state_str(), "1/2-1/2");
+}
+
+#[test]
+fn en_passant_only_counts_when_capturable() {
+    let mut board = Board::start_pos();
+    let mut info = PositionInfo::start_pos();
+    play(&mut board, &mut info, &["E2E4"]);
+    assert!(!position_key(&board, &info).contains("e3"));
+
+    play(&mut board, &mut info, &["A7A6", "E4E5", "D7D5"]);
+    assert!(position_key(&board, &info).ends_with(" d6"));
+
+    // only a pawn takes en passant, the king stepping onto d6 does not count
+    let (board, info) = fen_to_board("4k3/8/8/3pK3/8/8/8/8 w - d6 0 1").unwrap();
+    assert!(!position_key(&board, &info).contains("d6"));
+}
+
+#[test]
+fn results_without_a_move() {
+    assert_eq!(GameOver::resigned(Color::White).result, "0-1");
+    assert_eq!(GameOver::resigned(Color::Black).result, "1-0");
+    assert_eq!(GameOver::resigned(Color::Black).reason, "Black resigns");
+    assert_eq!(GameOver::draw_agreed().result, "1/2-1/2");
+    assert_eq!(GameOver::aborted(Color::White).result, "*");
+}
+
+#[test]
+fn flag_fall_needs_mating_material() {
+    let (board, _) = fen_to_board("4k3/8/8/8/8/8/8/R3K3 w - - 0 1").unwrap();
+    assert_eq!(GameOver::out_of_time(Color::Black, &board).result, "1-0");
+    // black ran out but white only has a knight
+    let (board, _) = fen_to_board("4k3/8/8/8/8/8/8/1N2K3 w - - 0 1").unwrap();
+    assert_eq!(GameOver::out_of_time(Color::Black, &board).result, "1/2-1/2");
+    assert_eq!(GameOver::out_of_time(Color::White, &board).result, "1/2-1/2");
+    let (board, _) = fen_to_board("4k2q/8/8/8/8/8/8/1N2K3 w - - 0 1").unwrap();
+    assert_eq!(GameOver::out_of_time(Color::White, &board).result, "0-1");
+}
@@ -0,0 +1,136 @@
+use std::sync::mpsc;
+use std::time::Duration;
+
+use eahla_gui::protocol::{ColorChoice, HelloMsg, ProtocolMsg};
+use eahla_gui::uci::{parse_uci_move, uci_move};
+use hermanha_chess::{PieceType, Position};
+
+#[test]
+fn uci_moves() {
+    assert_eq!(parse_uci_move("e2e4"), Some((Position::new(1, 4), Position::new(3, 4), None)));
+    assert_eq!(
+        parse_uci_move("a7a8n"),
+        Some((Position::new(6, 0), Position::new(7, 0), Some(PieceType::Knight)))
+    );
+    for bad in ["e2", "e2e9", "e7e8k", "e2e4e5", "(none)"] {
+        assert_eq!(parse_uci_move(bad), None, "{}", bad);
+    }
+    assert_eq!(uci_move(Position::new(6, 7), Position::new(7, 6), Some(PieceType::Queen)), "h7g8q");
+    assert_eq!(uci_move(Position::new(0, 4), Position::new(0, 6), None), "e1g1");
+}
+
+/// A stand-in engine that always answers e7e5, or e2e4 when it has the white pieces.
+/// Anything but a full six-field FEN leaves it without a move. It writes its process id
+/// next to itself, with ".pid" added.
+#[cfg(unix)]
+fn fake_engine(test: &str) -> std::path::PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = std::env::temp_dir().join(format!("fake-uci-engine-{}-{}", std::process::id(), test));
+    std::fs::write(
+        &path,
+        "#!/bin/sh\n\
+         echo $$ > \"$0.pid\"\n\
+         while read line; do\n\
+           case \"$line\" in\n\
+             uci) echo 'id name Fake'; echo uciok ;;\n\
+             isready) echo readyok ;;\n\
+             position*)\n\
+               set -- $line\n\
+               if [ $# -lt 8 ]; then next='bestmove (none)'\n\
+               elif [ $# -gt 8 ]; then echo 'info depth 1'; next='bestmove e7e5'\n\
+               else next='bestmove e2e4'; fi ;;\n\
+             go*) echo \"$next\" ;;\n\
+             quit) exit 0 ;;\n\
+           esac\n\
+         done\n",
+    )
+    .unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+    path
+}
+
+#[cfg(unix)]
+#[test]
+fn engine_answers_like_a_server() {
+    use eahla_gui::helper::{board_move_to_message, fen_to_board, make_move};
+    use eahla_gui::pgn::START_FEN;
+    use eahla_gui::protocol::QuitMsg;
+    use eahla_gui::status::GameStatus;
+    use eahla_gui::uci::start_engine_with_channel;
+
+    let engine = fake_engine("server");
+    let (to_engine, rx) = mpsc::channel();
+    let (tx, from_engine) = mpsc::channel();
+    let command = engine.to_str().unwrap().to_string();
+    let adapter = std::thread::spawn(move || start_engine_with_channel(&command, None, Duration::from_millis(10), rx, tx));
+    let recv = || from_engine.recv_timeout(Duration::from_secs(10)).unwrap().unwrap();
+
+    to_engine.send(ProtocolMsg::Hello(HelloMsg { color: ColorChoice::White, time: None })).unwrap();
+    let ProtocolMsg::Hello(hello) = recv() else { panic!("expected the handshake answer") };
+    assert_eq!(hello.color, ColorChoice::White);
+
+    let (mut board, mut info) = fen_to_board(START_FEN).unwrap();
+    let (from, to) = (Position::new(1, 4), Position::new(3, 4));
+    make_move(&mut board, &mut info, from, to, None).unwrap();
+    let msg = board_move_to_message(from, to, None, &board, &info, &GameStatus::Ongoing);
+    to_engine.send(ProtocolMsg::Move(msg)).unwrap();
+
+    let ProtocolMsg::Move(reply) = recv() else { panic!("expected the engine's move") };
+    assert_eq!(reply.move_str, "E7E50");
+    assert_eq!(reply.game_state, "0-0");
+    assert_eq!(reply.fen, "rnbqkbnr/pppp1ppp/8/4p3/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e6 0 2");
+
+    to_engine.send(ProtocolMsg::Quit(QuitMsg { reason: "User exited".to_string() })).unwrap();
+    adapter.join().unwrap().unwrap();
+    let _ = std::fs::remove_file(engine.with_extension("pid"));
+    let _ = std::fs::remove_file(engine);
+}
+
+#[cfg(unix)]
+#[test]
+fn engine_gets_a_full_fen() {
+    use eahla_gui::uci::start_engine_with_channel;
+
+    let engine = fake_engine("fen");
+    let (to_engine, rx) = mpsc::channel();
+    let (tx, from_engine) = mpsc::channel();
+    let command = engine.to_str().unwrap().to_string();
+    let placement = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR";
+    let adapter =
+        std::thread::spawn(move || start_engine_with_channel(&command, Some(placement), Duration::from_millis(10), rx, tx));
+    let recv = || from_engine.recv_timeout(Duration::from_secs(10)).unwrap().unwrap();
+
+    to_engine.send(ProtocolMsg::Hello(HelloMsg { color: ColorChoice::Black, time: None })).unwrap();
+    let ProtocolMsg::Hello(hello) = recv() else { panic!("expected the handshake answer") };
+    assert_eq!(hello.color, ColorChoice::Black);
+    let ProtocolMsg::Move(reply) = recv() else { panic!("expected the engine's move") };
+    assert_eq!(reply.move_str, "E2E40");
+
+    drop(to_engine);
+    adapter.join().unwrap().unwrap();
+    let _ = std::fs::remove_file(engine.with_extension("pid"));
+    let _ = std::fs::remove_file(engine);
+}
+
+#[cfg(unix)]
+#[test]
+fn engine_is_stopped_when_the_adapter_fails() {
+    use eahla_gui::uci::start_engine_with_channel;
+
+    let engine = fake_engine("bad-fen");
+    let (_to_engine, rx) = mpsc::channel();
+    let (tx, _from_engine) = mpsc::channel();
+    // the engine is up and has answered "uci" before the start position is looked at
+    let result = start_engine_with_channel(engine.to_str().unwrap(), Some("not a fen"), Duration::from_millis(10), rx, tx);
+    assert!(result.is_err());
+
+    let pid_file = engine.with_extension("pid");
+    let pid = std::fs::read_to_string(&pid_file).unwrap();
+    let _ = std::fs::remove_file(&pid_file);
+    let _ = std::fs::remove_file(&engine);
+    // an engine that is still running, or exited without being waited for, still has its pid
+    let kill = std::process::Command::new("kill").args(["-0", pid.trim()]).stderr(std::process::Stdio::null()).status();
+    let alive = kill.unwrap().success();
+    assert!(!alive, "engine {} was left behind", pid.trim());
+}